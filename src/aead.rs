@@ -0,0 +1,308 @@
+// Authenticated encryption with associated data. FeistelAead is encrypt-then-MAC: Feistel128 in
+// CTR mode, followed by HMAC-SHA3-256 over the nonce, the associated data and the ciphertext.
+// Decryption checks the tag before touching the ciphertext, so a wrong key or tampered data
+// gives AuthenticationFailed instead of garbage.
+//
+// The encryption and MAC keys are derived from the master key with SHAKE256, so one key can be
+// used for both. A nonce must never repeat under the same key.
+
+#[cfg(feature = "alloc")]
+use alloc::vec::Vec;
+use cipher::KeyInit;
+use core::fmt;
+use sha3::{Digest, Sha3_256};
+
+use crate::block::Feistel128;
+use crate::error::FeistelError;
+use crate::modes::Ctr;
+use crate::schedule::{KeySchedule, ShakeSchedule};
+
+pub(crate) const MAC_KEY_LEN: usize = 32;
+// SHA3-256 absorbs 136 bytes per block, HMAC pads its key to that length
+const SHA3_256_RATE: usize = 136;
+
+// HMAC-SHA3-256 over the concatenation of parts
+pub(crate) fn hmac_sha3(key: &[u8; MAC_KEY_LEN], parts: &[&[u8]]) -> [u8; 32] {
+    let mut ipad = [0x36u8; SHA3_256_RATE];
+    let mut opad = [0x5cu8; SHA3_256_RATE];
+    for (i, k) in key.iter().enumerate() {
+        ipad[i] ^= k;
+        opad[i] ^= k;
+    }
+    let mut inner = Sha3_256::new();
+    inner.input(&ipad[..]);
+    for part in parts {
+        inner.input(part);
+    }
+    let mut outer = Sha3_256::new();
+    outer.input(&opad[..]);
+    outer.input(inner.result());
+    let mut tag = [0u8; 32];
+    tag.copy_from_slice(&outer.result());
+    tag
+}
+
+// Compares two tags without an early exit
+pub(crate) fn tags_equal(a: &[u8], b: &[u8]) -> bool {
+    a.len() == b.len() && a.iter().zip(b).fold(0, |acc, (x, y)| acc | (x ^ y)) == 0
+}
+
+// Derives the block cipher and MAC key for one construction, separated by its context string
+pub(crate) fn derive_keys(key: &[u8], context: &[u8]) -> Result<(Feistel128, [u8; MAC_KEY_LEN]), FeistelError> {
+    if key.is_empty() {
+        return Err(FeistelError::EmptyKey);
+    }
+    let schedule = ShakeSchedule::new(context);
+    let mut enc_key = [0u8; 32];
+    schedule.derive(key, 0, &mut enc_key);
+    let mut mac_key = [0u8; MAC_KEY_LEN];
+    schedule.derive(key, 1, &mut mac_key);
+    Ok((Feistel128::new(&enc_key.into()), mac_key))
+}
+
+#[derive(Clone)]
+pub struct FeistelAead {
+    cipher: Feistel128,
+    mac_key: [u8; MAC_KEY_LEN],
+}
+
+impl FeistelAead {
+    pub const NONCE_LEN: usize = 12;
+    pub const TAG_LEN: usize = 32;
+    const CONTEXT: &'static [u8] = b"feistel_rs aead ctr hmac-sha3-256";
+
+    pub fn new(key: &[u8]) -> Result<Self, FeistelError> {
+        FeistelAead::with_context(key, FeistelAead::CONTEXT)
+    }
+
+    // Constructions built on FeistelAead (e.g. STREAM) use their own context, so their nonces can
+    // never collide with nonces used directly under the same master key
+    pub(crate) fn with_context(key: &[u8], context: &[u8]) -> Result<Self, FeistelError> {
+        let (cipher, mac_key) = derive_keys(key, context)?;
+        Ok(FeistelAead { cipher, mac_key })
+    }
+
+    // Returns ciphertext || tag
+    #[cfg(feature = "alloc")]
+    pub fn seal(&self, nonce: &[u8; 12], aad: &[u8], plaintext: &[u8]) -> Result<Vec<u8>, FeistelError> {
+        let mut sealed = Vec::with_capacity(plaintext.len() + FeistelAead::TAG_LEN);
+        sealed.extend_from_slice(plaintext);
+        let tag = self.seal_in_place_detached(nonce, aad, &mut sealed)?;
+        sealed.extend_from_slice(&tag);
+        Ok(sealed)
+    }
+
+    // Takes ciphertext || tag as returned by seal
+    #[cfg(feature = "alloc")]
+    pub fn open(&self, nonce: &[u8; 12], aad: &[u8], sealed: &[u8]) -> Result<Vec<u8>, FeistelError> {
+        if sealed.len() < FeistelAead::TAG_LEN {
+            return Err(FeistelError::AuthenticationFailed);
+        }
+        let (ciphertext, tag) = sealed.split_at(sealed.len() - FeistelAead::TAG_LEN);
+        let mut plaintext = ciphertext.to_vec();
+        self.open_in_place_detached(nonce, aad, &mut plaintext, tag)?;
+        Ok(plaintext)
+    }
+
+    // Encrypts buf in place and returns the tag
+    pub fn seal_in_place_detached(&self, nonce: &[u8; 12], aad: &[u8], buf: &mut [u8]) -> Result<[u8; 32], FeistelError> {
+        Ctr::new(self.cipher.clone(), nonce)?.apply_keystream(buf)?;
+        Ok(self.tag(nonce, aad, buf))
+    }
+
+    // Checks the tag and only then decrypts buf in place. On failure buf is left as it was.
+    pub fn open_in_place_detached(
+        &self,
+        nonce: &[u8; 12],
+        aad: &[u8],
+        buf: &mut [u8],
+        tag: &[u8],
+    ) -> Result<(), FeistelError> {
+        if !tags_equal(&self.tag(nonce, aad, buf), tag) {
+            return Err(FeistelError::AuthenticationFailed);
+        }
+        Ctr::new(self.cipher.clone(), nonce)?.apply_keystream(buf)
+    }
+
+    fn tag(&self, nonce: &[u8], aad: &[u8], ciphertext: &[u8]) -> [u8; 32] {
+        let aad_len = (aad.len() as u64).to_le_bytes();
+        let ct_len = (ciphertext.len() as u64).to_le_bytes();
+        hmac_sha3(&self.mac_key, &[nonce, aad, ciphertext, &aad_len, &ct_len])
+    }
+}
+
+// Keys are secret, so they are left out
+impl fmt::Debug for FeistelAead {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str("FeistelAead { .. }")
+    }
+}
+
+// Deterministic authenticated encryption in the SIV style: the tag is an HMAC-SHA3-256 over the
+// nonce, associated data and plaintext, and its first 12 bytes are the CTR nonce (synthetic IV).
+// Equal plaintexts under the same key, nonce and associated data give equal ciphertexts, which
+// makes deduplication possible. The nonce is optional: repeating it only reveals equal messages.
+//
+// seal and open look like those of FeistelAead, with two differences: the nonce is any byte string
+// (including none) instead of exactly 12 bytes, and the sealed layout is tag || ciphertext, as the
+// tag doubles as the IV, where FeistelAead returns ciphertext || tag.
+#[derive(Clone)]
+pub struct FeistelSiv {
+    cipher: Feistel128,
+    mac_key: [u8; MAC_KEY_LEN],
+}
+
+impl FeistelSiv {
+    pub const TAG_LEN: usize = 32;
+    // Bytes of the tag used as the CTR nonce
+    pub const SYNTHETIC_IV_LEN: usize = 12;
+    const CONTEXT: &'static [u8] = b"feistel_rs siv ctr hmac-sha3-256";
+
+    pub fn new(key: &[u8]) -> Result<Self, FeistelError> {
+        let (cipher, mac_key) = derive_keys(key, FeistelSiv::CONTEXT)?;
+        Ok(FeistelSiv { cipher, mac_key })
+    }
+
+    // Returns tag || ciphertext
+    #[cfg(feature = "alloc")]
+    pub fn seal(&self, nonce: &[u8], aad: &[u8], plaintext: &[u8]) -> Result<Vec<u8>, FeistelError> {
+        let mut sealed = Vec::with_capacity(FeistelSiv::TAG_LEN + plaintext.len());
+        sealed.extend_from_slice(&[0u8; FeistelSiv::TAG_LEN]);
+        sealed.extend_from_slice(plaintext);
+        let (tag, ciphertext) = sealed.split_at_mut(FeistelSiv::TAG_LEN);
+        tag.copy_from_slice(&self.seal_in_place_detached(nonce, aad, ciphertext)?);
+        Ok(sealed)
+    }
+
+    // Takes tag || ciphertext as returned by seal
+    #[cfg(feature = "alloc")]
+    pub fn open(&self, nonce: &[u8], aad: &[u8], sealed: &[u8]) -> Result<Vec<u8>, FeistelError> {
+        if sealed.len() < FeistelSiv::TAG_LEN {
+            return Err(FeistelError::AuthenticationFailed);
+        }
+        let (tag, ciphertext) = sealed.split_at(FeistelSiv::TAG_LEN);
+        let mut plaintext = ciphertext.to_vec();
+        self.open_in_place_detached(nonce, aad, &mut plaintext, tag)?;
+        Ok(plaintext)
+    }
+
+    // Encrypts buf in place and returns the tag
+    pub fn seal_in_place_detached(&self, nonce: &[u8], aad: &[u8], buf: &mut [u8]) -> Result<[u8; 32], FeistelError> {
+        let tag = self.tag(nonce, aad, buf);
+        self.apply_keystream(&tag, buf)?;
+        Ok(tag)
+    }
+
+    // Decrypts buf in place and checks the tag against the plaintext. On failure buf is zeroed,
+    // so unauthenticated plaintext is never handed out.
+    pub fn open_in_place_detached(
+        &self,
+        nonce: &[u8],
+        aad: &[u8],
+        buf: &mut [u8],
+        tag: &[u8],
+    ) -> Result<(), FeistelError> {
+        if tag.len() != FeistelSiv::TAG_LEN {
+            return Err(FeistelError::AuthenticationFailed);
+        }
+        self.apply_keystream(tag, buf)?;
+        if !tags_equal(&self.tag(nonce, aad, buf), tag) {
+            buf.fill(0);
+            return Err(FeistelError::AuthenticationFailed);
+        }
+        Ok(())
+    }
+
+    fn apply_keystream(&self, tag: &[u8], buf: &mut [u8]) -> Result<(), FeistelError> {
+        Ctr::new(self.cipher.clone(), &tag[..FeistelSiv::SYNTHETIC_IV_LEN])?.apply_keystream(buf)
+    }
+
+    fn tag(&self, nonce: &[u8], aad: &[u8], plaintext: &[u8]) -> [u8; 32] {
+        let nonce_len = (nonce.len() as u64).to_le_bytes();
+        let aad_len = (aad.len() as u64).to_le_bytes();
+        let pt_len = (plaintext.len() as u64).to_le_bytes();
+        hmac_sha3(&self.mac_key, &[nonce, aad, plaintext, &nonce_len, &aad_len, &pt_len])
+    }
+}
+
+impl fmt::Debug for FeistelSiv {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str("FeistelSiv { .. }")
+    }
+}
+
+#[cfg(all(test, feature = "std"))]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn hmac_sha3_256_matches_nist_example() {
+        let key: [u8; 32] = core::array::from_fn(|i| i as u8);
+        let tag = hmac_sha3(&key, &[b"Sample message for keylen<blocklen"]);
+        assert_eq!(
+            pretty_hex::simple_hex(&tag).replace(' ', ""),
+            "4fe8e202c4f058e8dddc23d8c34e467343e23555e24fc2f025d598f558f67205"
+        );
+    }
+
+    #[test]
+    fn seal_open_round_trip() {
+        let aead = FeistelAead::new(b"master key").unwrap();
+        let nonce = [7u8; 12];
+        for len in &[0, 1, 16, 33] {
+            let plaintext = vec![0xa5u8; *len];
+            let sealed = aead.seal(&nonce, b"header", &plaintext).unwrap();
+            assert_eq!(sealed.len(), len + FeistelAead::TAG_LEN);
+            assert_eq!(aead.open(&nonce, b"header", &sealed), Ok(plaintext));
+        }
+    }
+
+    #[test]
+    fn open_detects_tampering_and_wrong_key() {
+        let aead = FeistelAead::new(b"master key").unwrap();
+        let nonce = [7u8; 12];
+        let sealed = aead.seal(&nonce, b"header", b"attack at dawn").unwrap();
+        let failed = Err(FeistelError::AuthenticationFailed);
+        for i in 0..sealed.len() {
+            let mut tampered = sealed.clone();
+            tampered[i] ^= 1;
+            assert_eq!(aead.open(&nonce, b"header", &tampered), failed);
+        }
+        assert_eq!(aead.open(&nonce, b"footer", &sealed), failed);
+        assert_eq!(aead.open(&[8u8; 12], b"header", &sealed), failed);
+        assert_eq!(FeistelAead::new(b"other key").unwrap().open(&nonce, b"header", &sealed), failed);
+        assert_eq!(aead.open(&nonce, b"header", &sealed[..31]), failed);
+        assert_eq!(FeistelAead::new(b"").err(), Some(FeistelError::EmptyKey));
+    }
+
+    #[test]
+    fn siv_is_deterministic_per_associated_data() {
+        let siv = FeistelSiv::new(b"master key").unwrap();
+        let record = b"customer 42, 1 kg coffee";
+        let sealed = siv.seal(&[], b"orders", record).unwrap();
+        assert_eq!(sealed.len(), FeistelSiv::TAG_LEN + record.len());
+        assert_eq!(siv.seal(&[], b"orders", record).unwrap(), sealed);
+        assert_ne!(siv.seal(&[], b"invoices", record).unwrap(), sealed);
+        assert_ne!(siv.seal(b"nonce", b"orders", record).unwrap(), sealed);
+        assert_ne!(siv.seal(&[], b"orders", b"customer 43, 1 kg coffee").unwrap()[..32], sealed[..32]);
+        assert_eq!(siv.open(&[], b"orders", &sealed), Ok(record.to_vec()));
+    }
+
+    #[test]
+    fn siv_open_detects_tampering() {
+        let siv = FeistelSiv::new(b"master key").unwrap();
+        let sealed = siv.seal(b"n", b"orders", b"attack at dawn").unwrap();
+        let failed = Err(FeistelError::AuthenticationFailed);
+        for i in 0..sealed.len() {
+            let mut tampered = sealed.clone();
+            tampered[i] ^= 0x80;
+            assert_eq!(siv.open(b"n", b"orders", &tampered), failed);
+        }
+        assert_eq!(siv.open(b"m", b"orders", &sealed), failed);
+        assert_eq!(siv.open(b"n", b"order", &sealed), failed);
+        assert_eq!(siv.open(b"n", b"orders", &sealed[..20]), failed);
+        let mut buf = sealed[32..].to_vec();
+        assert_eq!(siv.open_in_place_detached(b"n", b"orders", &mut buf, &[0; 32]), Err(FeistelError::AuthenticationFailed));
+        assert_eq!(buf, [0; 14]);
+    }
+}
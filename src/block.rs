@@ -0,0 +1,119 @@
+// Fixed block size Feistel ciphers implementing the RustCrypto `cipher` traits (KeyInit,
+// BlockEncrypt, BlockDecrypt), so they plug into generic mode crates like ctr, cbc, cmac or xts-mode.
+//
+// Both use the default SHA3 round function and SHAKE key schedule with a 32 byte key and 16 rounds.
+// The round keys live in a fixed size array, so the types work without an allocator.
+
+use cipher::consts::{U16, U32, U8};
+use cipher::{impl_simple_block_encdec, AlgorithmName, BlockCipher, Key, KeyInit, KeySizeUser};
+use core::fmt;
+use sha3::Sha3_256;
+
+use crate::network;
+use crate::round::{RoundFunction, Sha3Round};
+use crate::schedule::{KeySchedule, ShakeSchedule};
+
+pub const ROUNDS: usize = 16;
+
+fn round_keys(key: &[u8]) -> [Sha3_256; ROUNDS] {
+    let schedule = ShakeSchedule::default();
+    core::array::from_fn(|i| {
+        let mut subkey = [0u8; ShakeSchedule::SUBKEY_LEN];
+        schedule.derive(key, i as u32, &mut subkey);
+        Sha3Round.round_key(&subkey, i as u32)
+    })
+}
+
+fn encrypt_block(round_keys: &[Sha3_256; ROUNDS], block: &mut [u8]) {
+    network::encrypt(block, ROUNDS as u32, |i, input, out| {
+        Sha3Round.apply(input, &round_keys[i as usize], &[], i, out)
+    });
+}
+
+fn decrypt_block(round_keys: &[Sha3_256; ROUNDS], block: &mut [u8]) {
+    network::decrypt(block, ROUNDS as u32, |i, input, out| {
+        Sha3Round.apply(input, &round_keys[i as usize], &[], i, out)
+    });
+}
+
+macro_rules! fixed_block_feistel {
+    ($name:ident, $block_size:ty, $doc:expr) => {
+        #[doc = $doc]
+        #[derive(Clone)]
+        pub struct $name {
+            round_keys: [Sha3_256; ROUNDS],
+        }
+
+        impl KeySizeUser for $name {
+            type KeySize = U32;
+        }
+
+        impl KeyInit for $name {
+            fn new(key: &Key<Self>) -> Self {
+                $name { round_keys: round_keys(key) }
+            }
+        }
+
+        impl BlockCipher for $name {}
+
+        impl AlgorithmName for $name {
+            fn write_alg_name(f: &mut fmt::Formatter<'_>) -> fmt::Result {
+                f.write_str(stringify!($name))
+            }
+        }
+
+        // Round keys are secret, so they are left out
+        impl fmt::Debug for $name {
+            fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+                f.write_str(concat!(stringify!($name), " { .. }"))
+            }
+        }
+
+        impl_simple_block_encdec!(
+            <> $name, $block_size, cipher, block,
+            encrypt: {
+                let mut b = block.clone_in();
+                encrypt_block(&cipher.round_keys, &mut b);
+                *block.get_out() = b;
+            }
+            decrypt: {
+                let mut b = block.clone_in();
+                decrypt_block(&cipher.round_keys, &mut b);
+                *block.get_out() = b;
+            }
+        );
+    };
+}
+
+fixed_block_feistel!(Feistel64, U8, "Feistel cipher with 64 bit blocks and a 256 bit key");
+fixed_block_feistel!(Feistel128, U16, "Feistel cipher with 128 bit blocks and a 256 bit key");
+
+#[cfg(all(test, feature = "std"))]
+mod tests {
+    use super::*;
+    use crate::FeistelCipher;
+    use cipher::generic_array::GenericArray;
+    use cipher::{BlockDecrypt, BlockEncrypt, KeyIvInit, StreamCipher};
+
+    #[test]
+    fn matches_feistel_cipher() {
+        let key = [7u8; 32];
+        let feistel = Feistel128::new(&key.into());
+        let mut block = GenericArray::clone_from_slice(b"sixteen byte msg");
+        feistel.encrypt_block(&mut block);
+        assert_eq!(block[..], FeistelCipher::new(&key, ROUNDS as u32).encrypt(b"sixteen byte msg")[..]);
+        feistel.decrypt_block(&mut block);
+        assert_eq!(&block[..], b"sixteen byte msg");
+    }
+
+    #[test]
+    fn works_with_ctr_mode() {
+        let mut data = *b"plugged into the generic ctr crate";
+        let mut ctr = ctr::Ctr64BE::<Feistel64>::new(&[1u8; 32].into(), &[2u8; 8].into());
+        ctr.apply_keystream(&mut data);
+        assert_ne!(&data, b"plugged into the generic ctr crate");
+        let mut ctr = ctr::Ctr64BE::<Feistel64>::new(&[1u8; 32].into(), &[2u8; 8].into());
+        ctr.apply_keystream(&mut data);
+        assert_eq!(&data, b"plugged into the generic ctr crate");
+    }
+}
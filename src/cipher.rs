@@ -0,0 +1,343 @@
+use alloc::{vec, vec::Vec};
+
+use crate::combiner::{Combiner, Xor};
+use crate::error::{self, FeistelError};
+use crate::network::{self, Split};
+use crate::round::{RoundFunction, Sha3Round};
+use crate::schedule::{KeySchedule, ShakeSchedule};
+
+// A Feistel cipher with a fixed key and number of rounds.
+// All round keys are derived once in the constructor, so encrypting many values under the same key
+// only pays for the round function. The cipher is Send + Sync whenever its round function is.
+// Halves are combined with XOR unless another Combiner is set with with_combiner.
+pub struct FeistelCipher<F: RoundFunction = Sha3Round, C = Xor> {
+    round_fn: F,
+    round_keys: Vec<F::RoundKey>,
+    split: Split,
+    combiner: C,
+}
+
+impl FeistelCipher {
+    // Cipher with the default SHA3 round function and SHAKE key schedule, same as feistel_encrypt
+    pub fn new(key: &[u8], rounds: u32) -> Self {
+        FeistelCipher::new_with(key, rounds, Sha3Round, &ShakeSchedule::default())
+    }
+
+    // Same as new, but rejects zero rounds and empty keys
+    pub fn try_new(key: &[u8], rounds: u32) -> Result<Self, FeistelError> {
+        FeistelCipher::try_new_with(key, rounds, Sha3Round, &ShakeSchedule::default())
+    }
+}
+
+impl<F: RoundFunction> FeistelCipher<F> {
+    // Cipher with a custom round function and key schedule, same as feistel_encrypt_with
+    pub fn new_with<S: KeySchedule>(key: &[u8], rounds: u32, round_fn: F, schedule: &S) -> Self {
+        let mut subkey = vec![0; schedule.subkey_len(key)];
+        let round_keys = (0..rounds).map(|i| {
+            schedule.derive(key, i, &mut subkey);
+            round_fn.round_key(&subkey, i)
+        }).collect();
+        FeistelCipher { round_fn, round_keys, split: Split::Balanced, combiner: Xor }
+    }
+
+    pub fn try_new_with<S: KeySchedule>(key: &[u8], rounds: u32, round_fn: F, schedule: &S) -> Result<Self, FeistelError> {
+        error::check_params(key, rounds)?;
+        Ok(FeistelCipher::new_with(key, rounds, round_fn, schedule))
+    }
+
+    // Encrypts the first `bits` bits of `data` in place, most significant bit of data[0] first.
+    // Any bits after that are left alone, so this gives permutations over 2^bits values.
+    pub fn encrypt_bits(&self, data: &mut [u8], bits: usize) -> Result<(), FeistelError> {
+        error::check_bits(data, bits)?;
+        self.encrypt_bits_in(data, bits, &mut vec![0; network::bit_scratch_len(bits)]);
+        Ok(())
+    }
+
+    pub fn decrypt_bits(&self, data: &mut [u8], bits: usize) -> Result<(), FeistelError> {
+        error::check_bits(data, bits)?;
+        self.decrypt_bits_in(data, bits, &mut vec![0; network::bit_scratch_len(bits)]);
+        Ok(())
+    }
+
+    // encrypt_bits with a caller provided scratch buffer of network::bit_scratch_len(bits) bytes
+    // and without checks, for callers that validated the bit length once up front
+    pub(crate) fn encrypt_bits_in(&self, data: &mut [u8], bits: usize, scratch: &mut [u8]) {
+        network::encrypt_bits(data, bits, self.rounds(), scratch, |i, input, out| self.round(i, input, &[], out));
+    }
+
+    pub(crate) fn decrypt_bits_in(&self, data: &mut [u8], bits: usize, scratch: &mut [u8]) {
+        network::decrypt_bits(data, bits, self.rounds(), scratch, |i, input, out| self.round(i, input, &[], out));
+    }
+}
+
+impl<F: RoundFunction, C: Combiner> FeistelCipher<F, C> {
+    // Combine halves with something other than XOR, e.g. AddModRadix for decimal digits.
+    // Only XOR keeps the in-place functions allocation free, other combiners need a scratch buffer.
+    pub fn with_combiner<D: Combiner>(self, combiner: D) -> FeistelCipher<F, D> {
+        FeistelCipher { round_fn: self.round_fn, round_keys: self.round_keys, split: self.split, combiner }
+    }
+
+    // Use an unbalanced network. encrypt and decrypt panic if the split does not fit the block,
+    // the try_ variants return FeistelError::InvalidSplit instead.
+    pub fn with_split(mut self, split: Split) -> Self {
+        self.split = split;
+        self
+    }
+
+    pub fn rounds(&self) -> u32 {
+        self.round_keys.len() as u32
+    }
+
+    pub fn split(&self) -> Split {
+        self.split
+    }
+
+    pub fn encrypt(&self, plaintext: &[u8]) -> Vec<u8> {
+        let mut block = plaintext.to_vec();
+        self.encrypt_in_place(&mut block);
+        block
+    }
+
+    pub fn decrypt(&self, ciphertext: &[u8]) -> Vec<u8> {
+        let mut block = ciphertext.to_vec();
+        self.decrypt_in_place(&mut block);
+        block
+    }
+
+    // Same as encrypt, but rejects blocks shorter than 2 bytes, splits that do not fit the block
+    // (including odd lengths under Split::Legacy) and blocks the combiner cannot handle
+    pub fn try_encrypt(&self, plaintext: &[u8]) -> Result<Vec<u8>, FeistelError> {
+        error::check_block(plaintext)?;
+        self.split.check_encrypt(plaintext.len())?;
+        self.combiner.check(plaintext)?;
+        Ok(self.encrypt(plaintext))
+    }
+
+    pub fn try_decrypt(&self, ciphertext: &[u8]) -> Result<Vec<u8>, FeistelError> {
+        error::check_block(ciphertext)?;
+        self.split.check(ciphertext.len())?;
+        self.combiner.check(ciphertext)?;
+        Ok(self.decrypt(ciphertext))
+    }
+
+    // Encrypts the block in the caller's buffer, without allocating when combining with XOR
+    pub fn encrypt_in_place(&self, block: &mut [u8]) {
+        self.encrypt_in_place_with_tweak(block, &[]);
+    }
+
+    // Decrypts the block in the caller's buffer, without allocating when combining with XOR
+    pub fn decrypt_in_place(&self, block: &mut [u8]) {
+        self.decrypt_in_place_with_tweak(block, &[]);
+    }
+
+    // Encrypts under a tweak, e.g. a column name or record ID. The tweak is passed to every round
+    // function call, so the same plaintext encrypts differently for every tweak without a new key.
+    // An empty tweak is the same as no tweak.
+    pub fn encrypt_with_tweak(&self, plaintext: &[u8], tweak: &[u8]) -> Vec<u8> {
+        let mut block = plaintext.to_vec();
+        self.encrypt_in_place_with_tweak(&mut block, tweak);
+        block
+    }
+
+    pub fn decrypt_with_tweak(&self, ciphertext: &[u8], tweak: &[u8]) -> Vec<u8> {
+        let mut block = ciphertext.to_vec();
+        self.decrypt_in_place_with_tweak(&mut block, tweak);
+        block
+    }
+
+    pub fn encrypt_in_place_with_tweak(&self, block: &mut [u8], tweak: &[u8]) {
+        let mut scratch = Vec::new();
+        let round = |i, input: &[u8], target: &mut [u8]| self.combine_round(i, input, tweak, target, &mut scratch, false);
+        match self.split {
+            Split::Balanced => network::encrypt(block, self.rounds(), round),
+            Split::Unbalanced { target } => network::encrypt_unbalanced(block, target, self.rounds(), round),
+            Split::Legacy => network::encrypt_alternating(block, self.rounds(), round),
+        }
+    }
+
+    pub fn decrypt_in_place_with_tweak(&self, block: &mut [u8], tweak: &[u8]) {
+        let mut scratch = Vec::new();
+        let round = |i, input: &[u8], target: &mut [u8]| self.combine_round(i, input, tweak, target, &mut scratch, true);
+        match self.split {
+            Split::Balanced => network::decrypt(block, self.rounds(), round),
+            Split::Unbalanced { target } => network::decrypt_unbalanced(block, target, self.rounds(), round),
+            Split::Legacy => network::decrypt_alternating(block, self.rounds(), round),
+        }
+    }
+
+    fn combine_round(&self, i: u32, input: &[u8], tweak: &[u8], target: &mut [u8], scratch: &mut Vec<u8>, decrypt: bool) {
+        if C::XOR {
+            // XOR is its own inverse, so the round function can work on the block directly
+            return self.round(i, input, tweak, target);
+        }
+        scratch.clear();
+        scratch.resize(self.combiner.output_len(target.len()), 0);
+        self.round(i, input, tweak, scratch);
+        if decrypt {
+            self.combiner.uncombine(target, scratch);
+        } else {
+            self.combiner.combine(target, scratch);
+        }
+    }
+
+    fn round(&self, i: u32, input: &[u8], tweak: &[u8], out: &mut [u8]) {
+        self.round_fn.apply(input, &self.round_keys[i as usize], tweak, i, out);
+    }
+}
+
+impl<F, C> Clone for FeistelCipher<F, C> where F: RoundFunction + Clone, F::RoundKey: Clone, C: Clone {
+    fn clone(&self) -> Self {
+        FeistelCipher {
+            round_fn: self.round_fn.clone(),
+            round_keys: self.round_keys.clone(),
+            split: self.split,
+            combiner: self.combiner.clone(),
+        }
+    }
+}
+
+#[cfg(all(test, feature = "std"))]
+mod tests {
+    use super::*;
+    use crate::{feistel_encrypt, feistel_encrypt_legacy, AddMod2n, AddModRadix, RotateSalt};
+    use std::sync::Arc;
+    use std::thread;
+
+    #[test]
+    fn matches_free_functions() {
+        let cipher = FeistelCipher::new(b"secret", 6);
+        let legacy = FeistelCipher::new_with(b"secret", 6, Sha3Round, &RotateSalt).with_split(Split::Legacy);
+        for plaintext in [&b"Feistel-rs"[..], b"odd length!", b"xy"].iter() {
+            assert_eq!(cipher.encrypt(plaintext), feistel_encrypt(plaintext, b"secret", 6));
+            assert_eq!(legacy.encrypt(plaintext), feistel_encrypt_legacy(plaintext, b"secret", 6));
+            assert_eq!(cipher.decrypt(&cipher.encrypt(plaintext)), *plaintext);
+        }
+    }
+
+    #[test]
+    fn try_api_rejects_invalid_input() {
+        assert!(FeistelCipher::try_new(b"secret", 0).is_err());
+        assert!(FeistelCipher::try_new(b"", 4).is_err());
+        let cipher = FeistelCipher::try_new(b"secret", 4).unwrap();
+        assert_eq!(cipher.try_encrypt(b"a"), Err(FeistelError::BlockTooShort { len: 1 }));
+        assert_eq!(cipher.try_decrypt(&cipher.try_encrypt(b"ab").unwrap()), Ok(b"ab".to_vec()));
+
+        let legacy = FeistelCipher::try_new_with(b"secret", 4, Sha3Round, &RotateSalt).unwrap();
+        assert_eq!(legacy.split(), Split::Balanced);
+        let legacy = legacy.with_split(Split::Legacy);
+        assert_eq!(legacy.try_encrypt(b"abc"), Err(FeistelError::InvalidSplit { target: 1, len: 3 }));
+        assert_eq!(legacy.try_decrypt(&legacy.encrypt(b"abc")), Ok(b"abc".to_vec()));
+        assert!(legacy.try_encrypt(b"abcd").is_ok());
+    }
+
+    #[test]
+    fn in_place_matches_vec_api() {
+        let cipher = FeistelCipher::new(b"secret", 7);
+        let mut block = *b"0123456789abcde";
+        cipher.encrypt_in_place(&mut block);
+        assert_eq!(block[..], cipher.encrypt(b"0123456789abcde")[..]);
+        cipher.decrypt_in_place(&mut block);
+        assert_eq!(&block, b"0123456789abcde");
+    }
+
+    #[test]
+    fn unbalanced_networks_are_invertible() {
+        let plaintext: Vec<u8> = (0..40).map(|_| rand::random::<u8>()).collect();
+        for rounds in 1..7 {
+            for len in 2..plaintext.len() {
+                for target in 1..len {
+                    let cipher = FeistelCipher::new(b"secret", rounds).with_split(Split::Unbalanced { target });
+                    let ciphertext = cipher.encrypt(&plaintext[..len]);
+                    assert_eq!(cipher.decrypt(&ciphertext), &plaintext[..len]);
+                }
+            }
+        }
+        let cipher = FeistelCipher::new(b"secret", 4).with_split(Split::Unbalanced { target: 4 });
+        assert_eq!(cipher.try_encrypt(b"abcd"), Err(FeistelError::InvalidSplit { target: 4, len: 4 }));
+    }
+
+    #[test]
+    fn bit_strings_of_any_length() {
+        let cipher = FeistelCipher::new(b"secret", 8);
+        for &bits in [2usize, 13, 37, 64, 129].iter() {
+            let plaintext: Vec<u8> = (0..bits.div_ceil(8) + 1).map(|_| rand::random::<u8>()).collect();
+            let mut data = plaintext.clone();
+            cipher.encrypt_bits(&mut data, bits).unwrap();
+            assert_eq!(data[bits.div_ceil(8)..], plaintext[bits.div_ceil(8)..]); // trailing bytes untouched
+            let mask = 0xffu8 >> (bits % 8); // as are the unused low bits of the last byte
+            if bits % 8 != 0 {
+                assert_eq!(data[bits / 8] & mask, plaintext[bits / 8] & mask);
+            }
+            cipher.decrypt_bits(&mut data, bits).unwrap();
+            assert_eq!(data, plaintext);
+        }
+        assert_eq!(cipher.encrypt_bits(&mut [0u8; 1], 9), Err(FeistelError::InvalidBitLength { bits: 9, buffer_len: 1 }));
+    }
+
+    #[test]
+    fn bit_cipher_is_a_permutation() {
+        let cipher = FeistelCipher::new(b"secret", 8);
+        let mut seen = vec![false; 1 << 10];
+        for v in 0u16..1 << 10 {
+            let mut data = (v << 6).to_be_bytes();
+            cipher.encrypt_bits(&mut data, 10).unwrap();
+            let c = u16::from_be_bytes(data) >> 6;
+            assert!(!seen[c as usize]);
+            seen[c as usize] = true;
+        }
+    }
+
+    #[test]
+    fn numeric_combiners() {
+        let decimal = FeistelCipher::new(b"secret", 10).with_combiner(AddModRadix::new(10).unwrap());
+        let plaintext = [4, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1];
+        let ciphertext = decimal.try_encrypt(&plaintext).unwrap();
+        assert!(ciphertext.iter().all(|&digit| digit < 10));
+        assert_ne!(ciphertext, plaintext);
+        assert_eq!(decimal.try_decrypt(&ciphertext).unwrap(), plaintext);
+        assert_eq!(decimal.try_encrypt(&[1, 10]), Err(FeistelError::InvalidNumeral { numeral: 10, radix: 10 }));
+
+        // Odd digit counts: the last digit is encrypted too, so changing it changes the whole output
+        let odd = decimal.try_encrypt(&plaintext[..15]).unwrap();
+        let mut changed = plaintext;
+        changed[14] = 7;
+        let other = decimal.try_encrypt(&changed[..15]).unwrap();
+        assert!(odd.iter().zip(&other).filter(|(a, b)| a != b).count() > 8);
+        assert_eq!(decimal.try_decrypt(&odd).unwrap(), &plaintext[..15]);
+        let legacy = FeistelCipher::new(b"secret", 10).with_split(Split::Legacy).with_combiner(AddModRadix::new(10).unwrap());
+        assert_eq!(legacy.try_encrypt(&plaintext[..15]), Err(FeistelError::InvalidSplit { target: 7, len: 15 }));
+
+        let added = FeistelCipher::new(b"secret", 10).with_combiner(AddMod2n).with_split(Split::Unbalanced { target: 3 });
+        let ciphertext = added.encrypt(b"Feistel-rs");
+        assert_eq!(added.decrypt(&ciphertext), b"Feistel-rs");
+    }
+
+    #[test]
+    fn tweaks_change_the_ciphertext() {
+        let cipher = FeistelCipher::new(b"secret", 8);
+        let plaintext = b"4111111111111111";
+        let by_name = cipher.encrypt_with_tweak(plaintext, b"column: name");
+        let by_card = cipher.encrypt_with_tweak(plaintext, b"column: card");
+        assert_ne!(by_name, by_card);
+        assert_eq!(cipher.encrypt_with_tweak(plaintext, b""), cipher.encrypt(plaintext));
+        assert_eq!(cipher.decrypt_with_tweak(&by_card, b"column: card"), plaintext);
+        assert_ne!(cipher.decrypt_with_tweak(&by_card, b"column: name"), plaintext);
+    }
+
+    #[test]
+    fn shared_across_threads() {
+        let cipher = Arc::new(FeistelCipher::new(b"secret", 8));
+        let handles: Vec<_> = (0u32..4).map(|t| {
+            let cipher = Arc::clone(&cipher);
+            thread::spawn(move || {
+                for v in 0u32..100 {
+                    let plaintext = (t * 1000 + v).to_be_bytes();
+                    assert_eq!(cipher.decrypt(&cipher.encrypt(&plaintext)), plaintext);
+                }
+            })
+        }).collect();
+        for handle in handles {
+            handle.join().unwrap();
+        }
+    }
+}
@@ -0,0 +1,177 @@
+// How the round function output is mixed into the target half.
+//
+// The textbook network uses XOR. Numeric constructions add the output instead (L + F(R) mod m) and
+// subtract it again on decryption, which keeps e.g. decimal digits decimal.
+
+use crate::error::FeistelError;
+
+pub trait Combiner {
+    // Set by Xor. Round functions XOR into their output buffer, so an XOR combiner lets them write
+    // straight into the block and the network needs no scratch buffer.
+    const XOR: bool = false;
+
+    // Bytes of round function output combine needs for a target of target_len bytes. Combiners
+    // that reduce the output ask for more than target_len so the reduction is not biased.
+    fn output_len(&self, target_len: usize) -> usize {
+        target_len
+    }
+
+    // target = target + output, output is output_len(target.len()) bytes long and may be
+    // overwritten, e.g. as scratch space for the reduction
+    fn combine(&self, target: &mut [u8], output: &mut [u8]);
+    // Inverse of combine, target = target - output
+    fn uncombine(&self, target: &mut [u8], output: &mut [u8]);
+
+    // Rejects blocks the combiner cannot work on, e.g. digits that are out of range
+    fn check(&self, _block: &[u8]) -> Result<(), FeistelError> {
+        Ok(())
+    }
+}
+
+// target ⊕ output, the default
+#[derive(Clone, Copy, Debug, Default)]
+pub struct Xor;
+
+impl Combiner for Xor {
+    const XOR: bool = true;
+
+    fn combine(&self, target: &mut [u8], output: &mut [u8]) {
+        for (t, o) in target.iter_mut().zip(output.iter()) {
+            *t ^= o;
+        }
+    }
+
+    fn uncombine(&self, target: &mut [u8], output: &mut [u8]) {
+        self.combine(target, output)
+    }
+}
+
+// Addition modulo 2^n, where the n bit halves are read as big endian integers
+#[derive(Clone, Copy, Debug, Default)]
+pub struct AddMod2n;
+
+impl Combiner for AddMod2n {
+    fn combine(&self, target: &mut [u8], output: &mut [u8]) {
+        let mut carry = 0u16;
+        for (t, &o) in target.iter_mut().zip(output.iter()).rev() {
+            let sum = u16::from(*t) + u16::from(o) + carry;
+            *t = sum as u8;
+            carry = sum >> 8;
+        }
+    }
+
+    fn uncombine(&self, target: &mut [u8], output: &mut [u8]) {
+        let mut borrow = 0i16;
+        for (t, &o) in target.iter_mut().zip(output.iter()).rev() {
+            let difference = i16::from(*t) - i16::from(o) - borrow;
+            *t = difference.rem_euclid(256) as u8;
+            borrow = i16::from(difference < 0);
+        }
+    }
+}
+
+// Addition modulo radix^k. Every byte of the block is one digit in 0..radix, most significant first,
+// so a k digit half is a number below radix^k. The round function output is read as a big endian
+// integer of k + 8 bytes and reduced mod radix^k, like the wide reduction in FF1, so the digits it
+// adds are uniform up to a bias below 2^-64.
+#[derive(Clone, Copy, Debug)]
+pub struct AddModRadix {
+    radix: u8,
+}
+
+impl AddModRadix {
+    const EXTRA_OUTPUT_LEN: usize = 8;
+
+    pub fn new(radix: u8) -> Result<Self, FeistelError> {
+        if radix < 2 {
+            return Err(FeistelError::InvalidDigitRadix { radix });
+        }
+        Ok(AddModRadix { radix })
+    }
+
+    pub fn radix(&self) -> u8 {
+        self.radix
+    }
+
+    // Divides the big endian integer in output by radix in place and returns the remainder
+    fn div_rem(&self, output: &mut [u8]) -> u8 {
+        let radix = u16::from(self.radix);
+        let mut remainder = 0u16;
+        for byte in output.iter_mut() {
+            let value = remainder << 8 | u16::from(*byte);
+            *byte = (value / radix) as u8;
+            remainder = value % radix;
+        }
+        remainder as u8
+    }
+}
+
+impl Combiner for AddModRadix {
+    fn output_len(&self, target_len: usize) -> usize {
+        target_len + AddModRadix::EXTRA_OUTPUT_LEN
+    }
+
+    fn combine(&self, target: &mut [u8], output: &mut [u8]) {
+        let radix = u16::from(self.radix);
+        let mut carry = 0u16;
+        for t in target.iter_mut().rev() {
+            let sum = u16::from(*t) + u16::from(self.div_rem(output)) + carry;
+            *t = (sum % radix) as u8;
+            carry = sum / radix;
+        }
+    }
+
+    fn uncombine(&self, target: &mut [u8], output: &mut [u8]) {
+        let radix = i16::from(self.radix);
+        let mut borrow = 0i16;
+        for t in target.iter_mut().rev() {
+            let difference = i16::from(*t) - i16::from(self.div_rem(output)) - borrow;
+            *t = difference.rem_euclid(radix) as u8;
+            borrow = i16::from(difference < 0);
+        }
+    }
+
+    fn check(&self, block: &[u8]) -> Result<(), FeistelError> {
+        match block.iter().find(|&&digit| digit >= self.radix) {
+            Some(&digit) => Err(FeistelError::InvalidNumeral { numeral: digit.into(), radix: self.radix.into() }),
+            None => Ok(()),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn modular_addition_carries() {
+        let mut target = [0x00, 0xff, 0xff];
+        AddMod2n.combine(&mut target, &mut [0x00, 0x00, 0x01]);
+        assert_eq!(target, [0x01, 0x00, 0x00]);
+        AddMod2n.uncombine(&mut target, &mut [0x00, 0x00, 0x01]);
+        assert_eq!(target, [0x00, 0xff, 0xff]);
+
+        let decimal = AddModRadix::new(10).unwrap();
+        assert_eq!(decimal.output_len(3), 11);
+        let mut target = [9, 9, 5];
+        let five = [0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 5];
+        decimal.combine(&mut target, &mut five.clone()); // 995 + 5 = 1000 = 000 mod 10^3
+        assert_eq!(target, [0, 0, 0]);
+        decimal.uncombine(&mut target, &mut five.clone());
+        assert_eq!(target, [9, 9, 5]);
+    }
+
+    #[test]
+    fn radix_digits_come_from_the_whole_output() {
+        let decimal = AddModRadix::new(10).unwrap();
+        // 256 mod 10 = 6, where reducing each byte on its own would give 1 mod 10 = 1
+        let mut target = [0];
+        decimal.combine(&mut target, &mut [0, 0, 0, 0, 0, 0, 0, 1, 0]);
+        assert_eq!(target, [6]);
+        // 2^72 - 1 = 4722366482869645213695
+        let mut target = [0, 0, 0];
+        decimal.combine(&mut target, &mut [0xff; 11][2..]);
+        assert_eq!(target, [6, 9, 5]);
+        assert_eq!(AddModRadix::new(1).err(), Some(FeistelError::InvalidDigitRadix { radix: 1 }));
+    }
+}
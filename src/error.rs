@@ -0,0 +1,110 @@
+use core::fmt;
+
+// Everything that can go wrong when setting up a cipher or processing a block
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum FeistelError {
+    // A network with zero rounds is the identity
+    ZeroRounds,
+    // An empty key makes every cipher with the same round count identical
+    EmptyKey,
+    // Blocks need at least one byte per half, otherwise a half never changes
+    BlockTooShort { len: usize },
+    // An unbalanced split needs 0 < target < block length
+    InvalidSplit { target: usize, len: usize },
+    // Bit strings need at least 2 bits and have to fit into the buffer
+    InvalidBitLength { bits: usize, buffer_len: usize },
+    // AES based modes take 16, 24 or 32 byte keys
+    InvalidKeyLength { len: usize },
+    // Numeral string modes support radixes from 2 to 2^16
+    InvalidRadix { radix: u32 },
+    // A numeral that is not smaller than the radix
+    InvalidNumeral { numeral: u16, radix: u32 },
+    // A numeral string whose domain is too small to be secure or too large for this implementation
+    InvalidNumeralLength { len: usize },
+    // A tweak whose length the mode does not accept
+    InvalidTweakLength { len: usize },
+    // A permutation over zero values
+    EmptyDomain,
+    // A string that is not valid in the expected encoding, or too large for the target integer
+    InvalidEncoding,
+    // Block modes without padding need whole blocks
+    UnalignedLength { len: usize, block_size: usize },
+    // An IV or nonce that does not fit the block size of the mode
+    InvalidIvLength { len: usize },
+    // The CTR counter ran out, more data would reuse keystream
+    KeystreamExhausted,
+    // Padding that does not match the chosen scheme, deliberately without details
+    InvalidPadding,
+    // The output buffer cannot hold the padded message
+    BufferTooSmall { len: usize, needed: usize },
+    // The tag did not match: wrong key, nonce or associated data, or tampered ciphertext
+    AuthenticationFailed,
+    // A stream segment that is not SEGMENT_LEN bytes long, or longer than that for the last one
+    InvalidSegmentLength { len: usize },
+    // Padding needs a block size from 1 to 255 bytes
+    InvalidBlockSize { block_size: usize },
+    // AddModRadix keeps one digit per byte, so it supports radixes from 2 to 255
+    InvalidDigitRadix { radix: u8 },
+}
+
+impl fmt::Display for FeistelError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            FeistelError::ZeroRounds => write!(f, "number of rounds must be at least 1"),
+            FeistelError::EmptyKey => write!(f, "key must not be empty"),
+            FeistelError::BlockTooShort { len } => write!(f, "block of {} bytes is too short, need at least 2", len),
+            FeistelError::InvalidSplit { target, len } => {
+                write!(f, "cannot update {} bytes of a {} byte block per round", target, len)
+            }
+            FeistelError::InvalidBitLength { bits, buffer_len } => {
+                write!(f, "cannot process {} bits in a {} byte buffer, need at least 2 bits", bits, buffer_len)
+            }
+            FeistelError::InvalidKeyLength { len } => write!(f, "AES key of {} bytes, need 16, 24 or 32", len),
+            FeistelError::InvalidRadix { radix } => write!(f, "radix {} is not between 2 and 65536", radix),
+            FeistelError::InvalidNumeral { numeral, radix } => write!(f, "numeral {} is out of range for radix {}", numeral, radix),
+            FeistelError::InvalidNumeralLength { len } => write!(f, "numeral string of length {} is outside the supported domain", len),
+            FeistelError::InvalidTweakLength { len } => write!(f, "tweak of {} bytes is not supported by this mode", len),
+            FeistelError::EmptyDomain => write!(f, "domain must contain at least one value"),
+            FeistelError::InvalidEncoding => write!(f, "invalid encoded value"),
+            FeistelError::UnalignedLength { len, block_size } => {
+                write!(f, "{} bytes is not a multiple of the block size {}", len, block_size)
+            }
+            FeistelError::InvalidIvLength { len } => write!(f, "IV or nonce of {} bytes does not fit the block size", len),
+            FeistelError::KeystreamExhausted => write!(f, "counter space exhausted, use a new nonce"),
+            FeistelError::InvalidPadding => write!(f, "invalid padding"),
+            FeistelError::BufferTooSmall { len, needed } => write!(f, "buffer of {} bytes is too small, need {}", len, needed),
+            FeistelError::AuthenticationFailed => write!(f, "authentication failed"),
+            FeistelError::InvalidSegmentLength { len } => write!(f, "stream segment of {} bytes has the wrong length", len),
+            FeistelError::InvalidBlockSize { block_size } => write!(f, "block size {} is not supported", block_size),
+            FeistelError::InvalidDigitRadix { radix } => write!(f, "digit radix {} is not between 2 and 255", radix),
+        }
+    }
+}
+
+#[cfg(feature = "std")]
+impl std::error::Error for FeistelError {}
+
+pub(crate) fn check_params(key: &[u8], rounds: u32) -> Result<(), FeistelError> {
+    if rounds == 0 {
+        return Err(FeistelError::ZeroRounds);
+    }
+    if key.is_empty() {
+        return Err(FeistelError::EmptyKey);
+    }
+    Ok(())
+}
+
+pub(crate) fn check_block(block: &[u8]) -> Result<(), FeistelError> {
+    if block.len() < 2 {
+        return Err(FeistelError::BlockTooShort { len: block.len() });
+    }
+    Ok(())
+}
+
+#[cfg(feature = "alloc")]
+pub(crate) fn check_bits(data: &[u8], bits: usize) -> Result<(), FeistelError> {
+    if bits < 2 || data.len() < bits.div_ceil(8) {
+        return Err(FeistelError::InvalidBitLength { bits, buffer_len: data.len() });
+    }
+    Ok(())
+}
@@ -1,10 +1,58 @@
+#![cfg_attr(not(feature = "std"), no_std)]
+
+#[cfg(feature = "alloc")]
+extern crate alloc;
+
+#[cfg(feature = "alloc")]
+use alloc::{vec, vec::Vec};
+#[cfg(feature = "std")]
 use std::io::prelude::*;
-use sha3::{Digest, Sha3_256};
 
+mod aead;
+mod block;
+#[cfg(feature = "alloc")]
+mod cipher;
+mod combiner;
+mod error;
+#[cfg(feature = "fpe")]
+pub mod ff1;
+#[cfg(feature = "fpe")]
+pub mod ff3_1;
+#[cfg(feature = "std")]
+mod io;
+pub mod modes;
+mod network;
+#[cfg(feature = "fpe")]
+mod numeral;
+#[cfg(feature = "alloc")]
+pub mod obfuscate;
+#[cfg(feature = "alloc")]
+mod permutation;
+mod round;
+mod schedule;
+mod stream;
+
+pub use aead::{FeistelAead, FeistelSiv};
+pub use block::{Feistel128, Feistel64};
+#[cfg(feature = "alloc")]
+pub use cipher::FeistelCipher;
+pub use combiner::{AddMod2n, AddModRadix, Combiner, Xor};
+pub use error::FeistelError;
+#[cfg(feature = "std")]
+pub use io::{DecryptingReader, EncryptingWriter};
+pub use network::Split;
+#[cfg(feature = "alloc")]
+pub use permutation::{shuffle_slice, DomainPermutation, PermutationIter};
+pub use round::{RoundFunction, Sha3Round};
+pub use schedule::{KeySchedule, RotateSalt, ShakeSchedule};
+pub use stream::{StreamDecryptor, StreamEncryptor};
+
+#[cfg(feature = "std")]
 pub trait WriteU32sLE<T> {
     fn write_u32s_le(&mut self, values: &[u32]) -> std::io::Result<usize>;
 }
 
+#[cfg(feature = "std")]
 impl<T> WriteU32sLE<T> for T where T : Write {
     fn write_u32s_le(&mut self, values: &[u32]) -> std::io::Result<usize> {
         for value in values {
@@ -15,128 +63,127 @@ impl<T> WriteU32sLE<T> for T where T : Write {
     }
 }
 
-// The round function in Feistel needs to be a strong PRF. We use sha3, as it is one.
-// Note that the round function does not need to be invertible
-// This func returns   sha3(subkey||data)[0-len(data)]
-fn round_fn(data: &[u8], subkey: &[u8]) -> Vec<u8> {
-    // PRF that takes two vectors and produces pseudorandom output.
-    // Note that the round function is not part of the Feistel cipher and should be set manually.
-    let mut hasher = Sha3_256::new();
-    hasher.input(subkey);
-    hasher.input(data); 
-    let hash = hasher.result().to_vec();
-    
-    let result: Vec<u8>; 
-    if data.len() < hash.len() {
-        result = hash[0..data.len()].to_vec(); // Round function needs to be length preserving
-    } else if data.len() == hash.len() {
-        result = hash;
-    } else { // data.len() > hash.len() = We cycle the hash to the desired length
-        result = hash.into_iter().cycle().take(data.len()).collect::<Vec<u8>>();
-    }
-    result
-    //result.to_vec()
+// Feistel encryption function that encrypts a byte slice, using another byte sliceas key
+// Subkeys come from the SHAKE based key schedule. Ciphertexts of older versions of the crate can be
+// decrypted with feistel_decrypt_legacy
+#[cfg(feature = "alloc")]
+pub fn feistel_encrypt(plaintext: &[u8], key: &[u8], rounds: u32) -> Vec<u8> {
+    let mut block = plaintext.to_vec();
+    feistel_encrypt_in_place(&mut block, key, rounds);
+    block
 }
 
-// Feistel encryption function that encrypts a byte slice, using another byte sliceas key
-pub fn feistel_encrypt(plaintext: &[u8], key: &[u8], rounds: u32) -> Vec<u8> { 
-    let mut _plaintext: &[u8] = plaintext.clone();
-    let plaintext_length: usize = _plaintext.len();
-    let (l, r) = _plaintext.split_at(plaintext_length / 2);
-    let mut left: Vec<u8> = l.to_vec();
-    let mut right: Vec<u8> = r.to_vec();
-
-    let mut subkey: Vec<u8>;
-    let mut tmp: Vec<u8>;
-    let mut salt: u32;
-    let mut updated_left: Vec<u8>;
-    let mut updated_right: Vec<u8>;
-
-    for i in 0..rounds {
-        // 1. Create round key
-        salt = key.iter().fold(0, |x, b| x+b.count_ones()) + i;
-        subkey = key.iter().map(|x| x.rotate_left(salt)).collect();
-
-        // L[i+1] = R[i]   Right side just moves to left side
-        updated_left = right.clone().to_vec();
-
-        // R[i+1] = L[i] ⊕ F(R[i], k[i])  Left side gets xored
-        updated_right = Vec::new();
-        tmp = round_fn(&right, &subkey);
-
-
-        // 2. Xor. if else handles unbalanced Feistel where len(Right) != len(Left)
-        if left.len() <= tmp.len() {
-            for i in 0..left.len() {
-                updated_right.push(left[i] ^ tmp[i]);
-            }
-        } else if left.len() > tmp.len() {
-            for i in 0..tmp.len() {
-                updated_right.push(left[i] ^ tmp[i]);
-            }
-            updated_right.push(left[left.len() - 1]);
-        }
+#[cfg(feature = "alloc")]
+pub fn feistel_decrypt(ciphertext: &[u8], key: &[u8], rounds: u32) -> Vec<u8> {
+    let mut block = ciphertext.to_vec();
+    feistel_decrypt_in_place(&mut block, key, rounds);
+    block
+}
 
-        // 3. swap left and right for next round
-        right = updated_right;
-        left = updated_left;
-    }
-    right.append(&mut left);
-    right
+// Same as feistel_encrypt, but rejects parameters that would not give a sensible cipher
+// (zero rounds, empty key, blocks shorter than 2 bytes) instead of processing them anyway
+#[cfg(feature = "alloc")]
+pub fn try_encrypt(plaintext: &[u8], key: &[u8], rounds: u32) -> Result<Vec<u8>, FeistelError> {
+    let mut block = plaintext.to_vec();
+    try_encrypt_in_place(&mut block, key, rounds)?;
+    Ok(block)
 }
 
+#[cfg(feature = "alloc")]
+pub fn try_decrypt(ciphertext: &[u8], key: &[u8], rounds: u32) -> Result<Vec<u8>, FeistelError> {
+    let mut block = ciphertext.to_vec();
+    try_decrypt_in_place(&mut block, key, rounds)?;
+    Ok(block)
+}
 
-pub fn feistel_decrypt(ciphertext: &[u8], key: &[u8], rounds: u32) -> Vec<u8> {
-    let mut _ciphertext: &[u8] = ciphertext.clone();
-    let ciphertext_length: usize = _ciphertext.len();
-    let split_index;
-    // Encryption gives us ciphertext of R + L for even amount of rounds
-    // ensure we split at the proper index if ciphertext has odd length
-    if (rounds % 2 == 0) && (ciphertext_length % 2 == 1) {
-        split_index = (ciphertext_length / 2) + 1;
-    } else {
-        split_index = ciphertext_length / 2;
-    }
-    let (l, r) = _ciphertext.split_at(split_index);
-    let mut left: Vec<u8> = l.to_vec();
-    let mut right: Vec<u8> = r.to_vec();
-
-    let mut subkey: Vec<u8>;
-    let mut tmp: Vec<u8>;
-    let mut salt: u32;
-    let mut updated_left: Vec<u8>;
-    let mut updated_right: Vec<u8>;
-
-    for i in 0..rounds {
-        salt = key.iter().fold(0, |x, b| x+b.count_ones()) + (rounds - i - 1);
-        subkey = key.iter().map(|x| x.rotate_left(salt)).collect();
-
-        // L[i+1] = R[i]
-        updated_left = right.clone().to_vec();
-
-        // R[i+1] = L[i] ⊕ F(R[i], k[i])
-        updated_right = Vec::new();
-        tmp = round_fn(&right, &subkey);
-
-        if left.len() <= tmp.len() {
-            for i in 0..left.len() {
-                updated_right.push(left[i] ^ tmp[i]);
-            }
-        } else if left.len() > tmp.len() {
-            for i in 0..tmp.len() {
-                updated_right.push(left[i] ^ tmp[i]);
-            }
-            updated_right.push(left[left.len() - 1]);
-        }
-        assert_eq!(right, updated_left);
-        right = updated_right;
-        left = updated_left;
-    }
-    right.append(&mut left);
-    right
+pub fn try_encrypt_in_place(block: &mut [u8], key: &[u8], rounds: u32) -> Result<(), FeistelError> {
+    error::check_params(key, rounds)?;
+    error::check_block(block)?;
+    feistel_encrypt_in_place(block, key, rounds);
+    Ok(())
 }
 
-#[cfg(test)]
+pub fn try_decrypt_in_place(block: &mut [u8], key: &[u8], rounds: u32) -> Result<(), FeistelError> {
+    error::check_params(key, rounds)?;
+    error::check_block(block)?;
+    feistel_decrypt_in_place(block, key, rounds);
+    Ok(())
+}
+
+// Same as feistel_encrypt, but encrypts the caller's buffer. Subkeys are derived on the fly into a
+// fixed size stack buffer, so this never touches the heap and is available without `alloc`.
+pub fn feistel_encrypt_in_place(block: &mut [u8], key: &[u8], rounds: u32) {
+    let schedule = ShakeSchedule::default();
+    let mut subkey = [0u8; ShakeSchedule::SUBKEY_LEN];
+    network::encrypt(block, rounds, |i, input, out| {
+        schedule.derive(key, i, &mut subkey);
+        Sha3Round.apply(input, &Sha3Round.round_key(&subkey, i), &[], i, out);
+    });
+}
+
+pub fn feistel_decrypt_in_place(block: &mut [u8], key: &[u8], rounds: u32) {
+    let schedule = ShakeSchedule::default();
+    let mut subkey = [0u8; ShakeSchedule::SUBKEY_LEN];
+    network::decrypt(block, rounds, |i, input, out| {
+        schedule.derive(key, i, &mut subkey);
+        Sha3Round.apply(input, &Sha3Round.round_key(&subkey, i), &[], i, out);
+    });
+}
+
+// Same as feistel_encrypt, but with a custom round function and key schedule
+#[cfg(feature = "alloc")]
+pub fn feistel_encrypt_with<F, S>(plaintext: &[u8], key: &[u8], rounds: u32, round_fn: &F, schedule: &S) -> Vec<u8>
+where F: RoundFunction, S: KeySchedule {
+    let mut block = plaintext.to_vec();
+    let mut subkey = vec![0; schedule.subkey_len(key)];
+    let round = |i, input: &[u8], out: &mut [u8]| {
+        schedule.derive(key, i, &mut subkey);
+        round_fn.apply(input, &round_fn.round_key(&subkey, i), &[], i, out);
+    };
+    network::encrypt(&mut block, rounds, round);
+    block
+}
+
+// Same as feistel_decrypt, but with a custom round function and key schedule
+#[cfg(feature = "alloc")]
+pub fn feistel_decrypt_with<F, S>(ciphertext: &[u8], key: &[u8], rounds: u32, round_fn: &F, schedule: &S) -> Vec<u8>
+where F: RoundFunction, S: KeySchedule {
+    let mut block = ciphertext.to_vec();
+    let mut subkey = vec![0; schedule.subkey_len(key)];
+    let round = |i, input: &[u8], out: &mut [u8]| {
+        schedule.derive(key, i, &mut subkey);
+        round_fn.apply(input, &round_fn.round_key(&subkey, i), &[], i, out);
+    };
+    network::decrypt(&mut block, rounds, round);
+    block
+}
+
+// The cipher of the first versions of the crate: Sha3Round with the RotateSalt schedule on the
+// original network layout (Split::Legacy). Odd length blocks keep their last byte in plaintext,
+// so this is only meant to reproduce and decrypt old ciphertexts.
+#[cfg(feature = "alloc")]
+pub fn feistel_encrypt_legacy(plaintext: &[u8], key: &[u8], rounds: u32) -> Vec<u8> {
+    let mut block = plaintext.to_vec();
+    let mut subkey = vec![0; RotateSalt.subkey_len(key)];
+    network::encrypt_alternating(&mut block, rounds, |i, input, out| {
+        RotateSalt.derive(key, i, &mut subkey);
+        Sha3Round.apply(input, &Sha3Round.round_key(&subkey, i), &[], i, out);
+    });
+    block
+}
+
+#[cfg(feature = "alloc")]
+pub fn feistel_decrypt_legacy(ciphertext: &[u8], key: &[u8], rounds: u32) -> Vec<u8> {
+    let mut block = ciphertext.to_vec();
+    let mut subkey = vec![0; RotateSalt.subkey_len(key)];
+    network::decrypt_alternating(&mut block, rounds, |i, input, out| {
+        RotateSalt.derive(key, i, &mut subkey);
+        Sha3Round.apply(input, &Sha3Round.round_key(&subkey, i), &[], i, out);
+    });
+    block
+}
+
+#[cfg(all(test, feature = "std"))]
 mod tests {
     use super::*;
     use pretty_hex::*;
@@ -144,8 +191,8 @@ mod tests {
     #[test]
     fn assert_functional_correctness() { // Assert that dec(enc(x))) == x and that enc(x) != x by testing many random inputs
         for i in 1..42 {
-            let random_bytes: Vec<u8> = (0..(i*32 + (i % 1)) ).map(|_| { rand::random::<u8>() }).collect();
-            let random_key: Vec<u8> = (0..(i*8 + (i % 1))).map(|_| { rand::random::<u8>() }).collect();
+            let random_bytes: Vec<u8> = (0..(i*32 + (i % 2)) ).map(|_| { rand::random::<u8>() }).collect();
+            let random_key: Vec<u8> = (0..(i*8 + (i % 2))).map(|_| { rand::random::<u8>() }).collect();
             let ciphertext = feistel_encrypt(&random_bytes, &random_key, i);
             let decrypted = feistel_decrypt(&ciphertext, &random_key, i);
             // Those prints show up if the test fails.
@@ -156,4 +203,70 @@ mod tests {
             assert_ne!(random_bytes, ciphertext);
         }
     }
-}
\ No newline at end of file
+
+    #[test]
+    fn known_ciphertexts() { // Ciphertexts produced by earlier versions of the crate must stay reproducible
+        let legacy = feistel_encrypt_legacy;
+        assert_eq!(legacy(b"Feistel-rs", b"secret", 4),
+                   [0xf1, 0x12, 0xc0, 0x9f, 0x1f, 0xa3, 0xf0, 0x35, 0xe1, 0x3b]);
+        assert_eq!(legacy(b"odd length!", b"k3y", 5),
+                   [0x38, 0x43, 0x3e, 0xcb, 0xc9, 0x50, 0x66, 0x5b, 0x7f, 0x7b, 0x21]);
+        assert_eq!(legacy(b"odd length!", b"k3y", 8),
+                   [0x83, 0xe8, 0x48, 0x61, 0x78, 0x21, 0x39, 0xff, 0xc7, 0xcb, 0x84]);
+        assert_ne!(feistel_encrypt(b"Feistel-rs", b"secret", 4), legacy(b"Feistel-rs", b"secret", 4));
+        assert_eq!(feistel_decrypt_legacy(&legacy(b"odd length!", b"k3y", 5), b"k3y", 5), b"odd length!");
+    }
+
+    #[test]
+    fn odd_lengths_encrypt_every_byte() {
+        let mut changed = b"abcdefghijklmno".to_vec();
+        changed[14] = b'X';
+        let ciphertext = feistel_encrypt(b"abcdefghijklmno", b"key", 8);
+        let other = feistel_encrypt(&changed, b"key", 8);
+        // The legacy layout copied the last byte to the ciphertext, now the change spreads everywhere
+        assert!(ciphertext.iter().zip(&other).filter(|(a, b)| a != b).count() > 12);
+        assert_eq!(feistel_decrypt(&ciphertext, b"key", 8), b"abcdefghijklmno");
+    }
+
+    #[test]
+    fn rejects_invalid_parameters() {
+        assert_eq!(try_encrypt(b"plaintext", b"key", 0), Err(FeistelError::ZeroRounds));
+        assert_eq!(try_encrypt(b"plaintext", b"", 4), Err(FeistelError::EmptyKey));
+        assert_eq!(try_encrypt(b"", b"key", 4), Err(FeistelError::BlockTooShort { len: 0 }));
+        assert_eq!(try_decrypt(b"x", b"key", 4), Err(FeistelError::BlockTooShort { len: 1 }));
+        let ciphertext = try_encrypt(b"xy", b"key", 4).unwrap();
+        assert_eq!(ciphertext, feistel_encrypt(b"xy", b"key", 4));
+        assert_eq!(try_decrypt(&ciphertext, b"key", 4).unwrap(), b"xy");
+        let ciphertext = try_encrypt(b"abcdefghijklmno", b"key", 8).unwrap();
+        assert_eq!(ciphertext, feistel_encrypt(b"abcdefghijklmno", b"key", 8));
+        assert_eq!(try_decrypt(&ciphertext, b"key", 8).unwrap(), b"abcdefghijklmno");
+    }
+
+    #[test]
+    fn custom_round_function() {
+        let weak = |input: &[u8], subkey: &[u8], _tweak: &[u8], round: u32, out: &mut [u8]| {
+            for (i, o) in out.iter_mut().enumerate() {
+                *o ^= input[i % input.len()] ^ subkey[i % subkey.len()] ^ round as u8;
+            }
+        };
+        let plaintext = b"attack at dawn";
+        let ciphertext = feistel_encrypt_with(plaintext, b"key", 7, &weak, &RotateSalt);
+        assert_ne!(ciphertext, feistel_encrypt(plaintext, b"key", 7));
+        assert_eq!(feistel_decrypt_with(&ciphertext, b"key", 7, &weak, &RotateSalt), plaintext);
+    }
+
+    #[test]
+    fn custom_key_schedule() {
+        struct Counter;
+        impl KeySchedule for Counter {
+            fn subkey_len(&self, _key: &[u8]) -> usize { 4 }
+            fn derive(&self, key: &[u8], round: u32, out: &mut [u8]) {
+                out.copy_from_slice(&(round ^ key.len() as u32).to_be_bytes());
+            }
+        }
+        let plaintext = b"attack at dawn";
+        let ciphertext = feistel_encrypt_with(plaintext, b"key", 5, &Sha3Round, &Counter);
+        assert_ne!(ciphertext, feistel_encrypt(plaintext, b"key", 5));
+        assert_eq!(feistel_decrypt_with(&ciphertext, b"key", 5, &Sha3Round, &Counter), plaintext);
+    }
+}
@@ -0,0 +1,183 @@
+// FF1 format preserving encryption from NIST SP 800-38G.
+//
+// FF1 is a 10 round alternating Feistel network over numeral strings that combines the halves by
+// addition modulo radix^m instead of XOR. The round function is a CBC-MAC over AES.
+// It runs on the same round loop as the byte network (network::alternate): with u = n/2 the target
+// of even rounds is X[..u], the target of odd rounds X[u..], which is exactly FF1's A/B swapping.
+
+#[cfg(feature = "alloc")]
+use alloc::vec::Vec;
+
+use crate::error::FeistelError;
+use crate::network;
+use crate::numeral::{self, Aes};
+
+const ROUNDS: u32 = 10;
+
+#[derive(Clone)]
+pub struct Ff1 {
+    aes: Aes,
+    radix: u32,
+}
+
+impl Ff1 {
+    // FF1 with an AES-128, AES-192 or AES-256 key over numerals in 0..radix
+    pub fn new(key: &[u8], radix: u32) -> Result<Self, FeistelError> {
+        numeral::check_radix(radix)?;
+        Ok(Ff1 { aes: Aes::new(key)?, radix })
+    }
+
+    pub fn radix(&self) -> u32 {
+        self.radix
+    }
+
+    #[cfg(feature = "alloc")]
+    pub fn encrypt(&self, tweak: &[u8], numerals: &[u16]) -> Result<Vec<u16>, FeistelError> {
+        let mut block = numerals.to_vec();
+        self.encrypt_in_place(tweak, &mut block)?;
+        Ok(block)
+    }
+
+    #[cfg(feature = "alloc")]
+    pub fn decrypt(&self, tweak: &[u8], numerals: &[u16]) -> Result<Vec<u16>, FeistelError> {
+        let mut block = numerals.to_vec();
+        self.decrypt_in_place(tweak, &mut block)?;
+        Ok(block)
+    }
+
+    pub fn encrypt_in_place(&self, tweak: &[u8], numerals: &mut [u16]) -> Result<(), FeistelError> {
+        let params = self.params(tweak, numerals)?;
+        network::alternate(numerals, params.u, 0..ROUNDS, |i, source, target| {
+            let modulus = params.modulus(i);
+            let y = self.round(&params, tweak, i, source) % modulus;
+            let c = (numeral::num(self.radix, target) + y) % modulus;
+            numeral::write_str(self.radix, c, target);
+        });
+        Ok(())
+    }
+
+    pub fn decrypt_in_place(&self, tweak: &[u8], numerals: &mut [u16]) -> Result<(), FeistelError> {
+        let params = self.params(tweak, numerals)?;
+        network::alternate(numerals, params.u, (0..ROUNDS).rev(), |i, source, target| {
+            let modulus = params.modulus(i);
+            let y = self.round(&params, tweak, i, source) % modulus;
+            let c = (numeral::num(self.radix, target) + modulus - y) % modulus;
+            numeral::write_str(self.radix, c, target);
+        });
+        Ok(())
+    }
+
+    // Steps 1 to 5 of FF1: lengths and the CBC-MAC state after the fixed block P
+    fn params(&self, tweak: &[u8], numerals: &[u16]) -> Result<Params, FeistelError> {
+        let n = numerals.len();
+        let invalid = FeistelError::InvalidNumeralLength { len: n };
+        numeral::check_numerals(self.radix, numerals)?;
+        if n < 2 || numeral::pow(self.radix, n).is_some_and(|domain| domain < numeral::MIN_DOMAIN) {
+            return Err(invalid);
+        }
+        let u = n / 2;
+        let v = n - u;
+        let modulus_u = numeral::pow(self.radix, u).ok_or(invalid)?;
+        let modulus_v = numeral::pow(self.radix, v).ok_or(invalid)?;
+        if modulus_v > numeral::MAX_HALF_DOMAIN {
+            return Err(invalid);
+        }
+        // b = ceil(ceil(v * log2(radix)) / 8), d = 4 * ceil(b / 4) + 4
+        let b = (128 - (modulus_v - 1).leading_zeros() as usize).div_ceil(8);
+        let d = 4 * b.div_ceil(4) + 4;
+
+        let mut p = [1, 2, 1, 0, 0, 0, 10, u as u8, 0, 0, 0, 0, 0, 0, 0, 0];
+        p[3..6].copy_from_slice(&self.radix.to_be_bytes()[1..]);
+        p[8..12].copy_from_slice(&(n as u32).to_be_bytes());
+        p[12..16].copy_from_slice(&(tweak.len() as u32).to_be_bytes());
+        self.aes.encrypt(&mut p);
+        Ok(Params { u, b, d, modulus_u, modulus_v, mac_p: p })
+    }
+
+    // y = NUM(S) where S is the first d bytes of PRF(P || Q)
+    fn round(&self, params: &Params, tweak: &[u8], i: u32, source: &[u16]) -> u128 {
+        let mut mac = CbcMac { aes: &self.aes, state: params.mac_p, pos: 0 };
+        mac.update(tweak);
+        let padding = (16 - (tweak.len() + params.b + 1) % 16) % 16;
+        for _ in 0..padding {
+            mac.update(&[0]);
+        }
+        mac.update(&[i as u8]);
+        mac.update(&numeral::num(self.radix, source).to_be_bytes()[16 - params.b..]);
+        debug_assert_eq!(mac.pos, 0);
+        // d <= 16, so S never needs more than the first block of R
+        mac.state[..params.d].iter().fold(0, |acc, &x| acc << 8 | u128::from(x))
+    }
+}
+
+struct Params {
+    u: usize,
+    b: usize,
+    d: usize,
+    modulus_u: u128,
+    modulus_v: u128,
+    mac_p: [u8; 16],
+}
+
+impl Params {
+    // radix^m, where m is the length of the half that gets updated in round i
+    fn modulus(&self, i: u32) -> u128 {
+        if i.is_multiple_of(2) { self.modulus_u } else { self.modulus_v }
+    }
+}
+
+// CBC-MAC with a zero IV over input whose length is a multiple of the block size
+struct CbcMac<'a> {
+    aes: &'a Aes,
+    state: [u8; 16],
+    pos: usize,
+}
+
+impl CbcMac<'_> {
+    fn update(&mut self, data: &[u8]) {
+        for &x in data {
+            self.state[self.pos] ^= x;
+            self.pos += 1;
+            if self.pos == 16 {
+                self.aes.encrypt(&mut self.state);
+                self.pos = 0;
+            }
+        }
+    }
+}
+
+#[cfg(all(test, feature = "std"))]
+mod tests {
+    use super::*;
+    use crate::numeral::test_helpers::{hex, numerals};
+
+    // Samples from https://csrc.nist.gov/CSRC/media/Projects/Cryptographic-Standards-and-Guidelines/documents/examples/FF1samples.pdf
+    #[test]
+    fn nist_samples() {
+        let samples = [
+            ("2B7E151628AED2A6ABF7158809CF4F3C", 10, "", "0123456789", "2433477484"),
+            ("2B7E151628AED2A6ABF7158809CF4F3C", 10, "39383736353433323130", "0123456789", "6124200773"),
+            ("2B7E151628AED2A6ABF7158809CF4F3C", 36, "3737373770717273373737", "0123456789abcdefghi", "a9tv40mll9kdu509eum"),
+            ("2B7E151628AED2A6ABF7158809CF4F3CEF4359D8D580AA4F", 10, "", "0123456789", "2830668132"),
+            ("2B7E151628AED2A6ABF7158809CF4F3CEF4359D8D580AA4F7F036D6F04FC6A94", 10, "", "0123456789", "6657667009"),
+        ];
+        for &(key, radix, tweak, plaintext, ciphertext) in samples.iter() {
+            let ff1 = Ff1::new(&hex(key), radix).unwrap();
+            let encrypted = ff1.encrypt(&hex(tweak), &numerals(plaintext)).unwrap();
+            assert_eq!(encrypted, numerals(ciphertext));
+            assert_eq!(ff1.decrypt(&hex(tweak), &encrypted).unwrap(), numerals(plaintext));
+        }
+    }
+
+    #[test]
+    fn rejects_invalid_input() {
+        let key = hex("2B7E151628AED2A6ABF7158809CF4F3C");
+        assert_eq!(Ff1::new(&key[..15], 10).err(), Some(FeistelError::InvalidKeyLength { len: 15 }));
+        assert_eq!(Ff1::new(&key, 1).err(), Some(FeistelError::InvalidRadix { radix: 1 }));
+        let ff1 = Ff1::new(&key, 10).unwrap();
+        assert_eq!(ff1.encrypt(b"", &numerals("12345")), Err(FeistelError::InvalidNumeralLength { len: 5 }));
+        assert_eq!(ff1.encrypt(b"", &numerals("12345a")), Err(FeistelError::InvalidNumeral { numeral: 10, radix: 10 }));
+        assert!(ff1.encrypt(b"", &[9; 56]).is_ok());
+        assert!(ff1.encrypt(b"", &[9; 57]).is_err()); // 10^29 does not fit the half domain
+    }
+}
@@ -0,0 +1,178 @@
+// FF3-1 format preserving encryption from NIST SP 800-38G Revision 1.
+//
+// FF3-1 is an 8 round alternating Feistel network over numeral strings with a 56 bit tweak that is
+// split into two 32 bit halves, one for even and one for odd rounds. Numbers are read with the
+// least significant numeral first (the REV in the standard) and AES is used with a byte reversed key.
+// Like FF1 it runs on network::alternate, here with u = ceil(n/2).
+
+#[cfg(feature = "alloc")]
+use alloc::vec::Vec;
+
+use crate::error::FeistelError;
+use crate::network;
+use crate::numeral::{self, Aes};
+
+const ROUNDS: u32 = 8;
+pub const TWEAK_LEN: usize = 7;
+
+#[derive(Clone)]
+pub struct Ff3_1 {
+    aes: Aes,
+    radix: u32,
+}
+
+impl Ff3_1 {
+    // FF3-1 with an AES-128, AES-192 or AES-256 key over numerals in 0..radix
+    pub fn new(key: &[u8], radix: u32) -> Result<Self, FeistelError> {
+        numeral::check_radix(radix)?;
+        if key.len() > 32 {
+            return Err(FeistelError::InvalidKeyLength { len: key.len() });
+        }
+        let mut reversed = [0u8; 32];
+        let reversed = &mut reversed[..key.len()];
+        reversed.copy_from_slice(key);
+        reversed.reverse();
+        Ok(Ff3_1 { aes: Aes::new(reversed)?, radix })
+    }
+
+    pub fn radix(&self) -> u32 {
+        self.radix
+    }
+
+    #[cfg(feature = "alloc")]
+    pub fn encrypt(&self, tweak: &[u8], numerals: &[u16]) -> Result<Vec<u16>, FeistelError> {
+        let mut block = numerals.to_vec();
+        self.encrypt_in_place(tweak, &mut block)?;
+        Ok(block)
+    }
+
+    #[cfg(feature = "alloc")]
+    pub fn decrypt(&self, tweak: &[u8], numerals: &[u16]) -> Result<Vec<u16>, FeistelError> {
+        let mut block = numerals.to_vec();
+        self.decrypt_in_place(tweak, &mut block)?;
+        Ok(block)
+    }
+
+    pub fn encrypt_in_place(&self, tweak: &[u8], numerals: &mut [u16]) -> Result<(), FeistelError> {
+        let tweak = split_tweak(tweak)?;
+        self.encrypt_with_tweak_halves(tweak, numerals)
+    }
+
+    pub fn decrypt_in_place(&self, tweak: &[u8], numerals: &mut [u16]) -> Result<(), FeistelError> {
+        let tweak = split_tweak(tweak)?;
+        self.decrypt_with_tweak_halves(tweak, numerals)
+    }
+
+    // The rounds of FF3-1 are the rounds of the original FF3, only the tweak halves differ
+    fn encrypt_with_tweak_halves(&self, tweak: [[u8; 4]; 2], numerals: &mut [u16]) -> Result<(), FeistelError> {
+        let (u, moduli) = self.params(numerals)?;
+        network::alternate(numerals, u, 0..ROUNDS, |i, source, target| {
+            let modulus = moduli[i as usize % 2];
+            let y = self.round(&tweak, i, source) % modulus;
+            let c = (numeral::num_rev(self.radix, target) + y) % modulus;
+            numeral::write_str_rev(self.radix, c, target);
+        });
+        Ok(())
+    }
+
+    fn decrypt_with_tweak_halves(&self, tweak: [[u8; 4]; 2], numerals: &mut [u16]) -> Result<(), FeistelError> {
+        let (u, moduli) = self.params(numerals)?;
+        network::alternate(numerals, u, (0..ROUNDS).rev(), |i, source, target| {
+            let modulus = moduli[i as usize % 2];
+            let y = self.round(&tweak, i, source) % modulus;
+            let c = (numeral::num_rev(self.radix, target) + modulus - y) % modulus;
+            numeral::write_str_rev(self.radix, c, target);
+        });
+        Ok(())
+    }
+
+    // u = ceil(n/2) and radix^u, radix^v. The larger half has to fit into 96 bits.
+    fn params(&self, numerals: &[u16]) -> Result<(usize, [u128; 2]), FeistelError> {
+        let n = numerals.len();
+        let invalid = FeistelError::InvalidNumeralLength { len: n };
+        numeral::check_numerals(self.radix, numerals)?;
+        if n < 2 || numeral::pow(self.radix, n).is_some_and(|domain| domain < numeral::MIN_DOMAIN) {
+            return Err(invalid);
+        }
+        let u = n.div_ceil(2);
+        let modulus_u = numeral::pow(self.radix, u).ok_or(invalid)?;
+        if modulus_u > numeral::MAX_HALF_DOMAIN {
+            return Err(invalid);
+        }
+        let modulus_v = numeral::pow(self.radix, n - u).ok_or(invalid)?;
+        Ok((u, [modulus_u, modulus_v]))
+    }
+
+    // y = NUM(REVB(CIPH(REVB(W ⊕ [i]^4 || [NUM_radix(REV(B))]^12))))
+    fn round(&self, tweak: &[[u8; 4]; 2], i: u32, source: &[u16]) -> u128 {
+        // Even rounds use T_R, odd rounds T_L
+        let w = if i.is_multiple_of(2) { tweak[1] } else { tweak[0] };
+        let mut p = [0u8; 16];
+        p[..4].copy_from_slice(&(u32::from_be_bytes(w) ^ i).to_be_bytes());
+        p[4..].copy_from_slice(&numeral::num_rev(self.radix, source).to_be_bytes()[4..]);
+        p.reverse();
+        self.aes.encrypt(&mut p);
+        u128::from_le_bytes(p)
+    }
+}
+
+// T_L = T[0..28] || 0^4 and T_R = T[32..56] || T[28..32] || 0^4
+fn split_tweak(tweak: &[u8]) -> Result<[[u8; 4]; 2], FeistelError> {
+    if tweak.len() != TWEAK_LEN {
+        return Err(FeistelError::InvalidTweakLength { len: tweak.len() });
+    }
+    let left = [tweak[0], tweak[1], tweak[2], tweak[3] & 0xf0];
+    let right = [tweak[4], tweak[5], tweak[6], tweak[3] << 4];
+    Ok([left, right])
+}
+
+#[cfg(all(test, feature = "std"))]
+mod tests {
+    use super::*;
+    use crate::numeral::test_helpers::{hex, numerals};
+
+    // Samples for the original FF3 with 64 bit tweaks from
+    // https://csrc.nist.gov/CSRC/media/Projects/Cryptographic-Standards-and-Guidelines/documents/examples/FF3samples.pdf
+    // They pin down the rounds, which FF3-1 did not change.
+    #[test]
+    fn nist_ff3_samples() {
+        let samples = [
+            ("EF4359D8D580AA4F7F036D6F04FC6A94", 10, "D8E7920AFA330A73", "890121234567890000", "750918814058654607"),
+            ("EF4359D8D580AA4F7F036D6F04FC6A94", 10, "9A768A92F60E12D8", "890121234567890000", "018989839189395384"),
+            ("EF4359D8D580AA4F7F036D6F04FC6A94", 10, "D8E7920AFA330A73", "89012123456789000000789000000", "48598367162252569629397416226"),
+            ("EF4359D8D580AA4F7F036D6F04FC6A94", 10, "0000000000000000", "89012123456789000000789000000", "34695224821734535122613701434"),
+            ("EF4359D8D580AA4F7F036D6F04FC6A94", 26, "9A768A92F60E12D8", "0123456789abcdefghi", "g2pk40i992fn20cjakb"),
+        ];
+        for &(key, radix, tweak, plaintext, ciphertext) in samples.iter() {
+            let ff3 = Ff3_1::new(&hex(key), radix).unwrap();
+            let tweak = hex(tweak);
+            let halves = [[tweak[0], tweak[1], tweak[2], tweak[3]], [tweak[4], tweak[5], tweak[6], tweak[7]]];
+            let mut block = numerals(plaintext);
+            ff3.encrypt_with_tweak_halves(halves, &mut block).unwrap();
+            assert_eq!(block, numerals(ciphertext));
+            ff3.decrypt_with_tweak_halves(halves, &mut block).unwrap();
+            assert_eq!(block, numerals(plaintext));
+        }
+    }
+
+    // FF3-1 sample with a 56 bit tweak
+    #[test]
+    fn ff3_1_sample() {
+        let ff3 = Ff3_1::new(&hex("2DE79D232DF5585D68CE47882AE256D6"), 10).unwrap();
+        let tweak = hex("CBD09280979564");
+        let ciphertext = ff3.encrypt(&tweak, &numerals("3992520240")).unwrap();
+        assert_eq!(ciphertext, numerals("8901801106"));
+        assert_eq!(ff3.decrypt(&tweak, &ciphertext).unwrap(), numerals("3992520240"));
+    }
+
+    #[test]
+    fn rejects_invalid_input() {
+        let ff3 = Ff3_1::new(&hex("EF4359D8D580AA4F7F036D6F04FC6A94"), 10).unwrap();
+        let tweak = hex("D8E7920AFA330A");
+        assert_eq!(ff3.encrypt(&tweak[..6], &numerals("123456")), Err(FeistelError::InvalidTweakLength { len: 6 }));
+        assert_eq!(ff3.encrypt(&tweak, &numerals("12345")), Err(FeistelError::InvalidNumeralLength { len: 5 }));
+        assert!(ff3.encrypt(&tweak, &[9; 56]).is_ok());
+        assert!(ff3.encrypt(&tweak, &[9; 57]).is_err()); // u = 29 and 10^29 > 2^96
+        assert_eq!(Ff3_1::new(&[0; 33], 10).err(), Some(FeistelError::InvalidKeyLength { len: 33 }));
+    }
+}
@@ -0,0 +1,387 @@
+// std::io adapters for the segmented stream format of StreamEncryptor/StreamDecryptor, which seals
+// every segment with Feistel128 in CTR mode and HMAC-SHA3-256. They plug into io::copy, compression
+// pipelines or sockets while holding at most one segment in memory.
+
+use std::io::{self, Read, Write};
+
+use crate::aead::FeistelAead;
+use crate::error::FeistelError;
+use crate::stream::{StreamDecryptor, StreamEncryptor};
+
+const SEALED_SEGMENT_LEN: usize = StreamEncryptor::SEGMENT_LEN + FeistelAead::TAG_LEN;
+
+fn io_error(err: FeistelError) -> io::Error {
+    let kind = match err {
+        FeistelError::AuthenticationFailed => io::ErrorKind::InvalidData,
+        _ => io::ErrorKind::InvalidInput,
+    };
+    io::Error::new(kind, err)
+}
+
+// Reads into buf[*filled..] until buf is full or the reader is exhausted. `filled` is kept up to
+// date, so the caller knows how much was read even if the reader fails halfway.
+fn read_full<R: Read>(reader: &mut R, buf: &mut [u8], filled: &mut usize) -> io::Result<()> {
+    while *filled < buf.len() {
+        match reader.read(&mut buf[*filled..]) {
+            Ok(0) => break,
+            Ok(n) => *filled += n,
+            Err(ref e) if e.kind() == io::ErrorKind::Interrupted => {}
+            Err(e) => return Err(e),
+        }
+    }
+    Ok(())
+}
+
+// Encrypts everything written to it into the inner writer. The stream is only complete after
+// finish or try_finish, which seal the last segment: a writer that is just dropped leaves a
+// truncated stream that DecryptingReader rejects.
+//
+// Sealed bytes stay buffered until the inner writer took all of them, so a write or try_finish
+// that fails with e.g. WouldBlock or Interrupted can simply be retried.
+pub struct EncryptingWriter<W: Write> {
+    inner: W,
+    encryptor: Option<StreamEncryptor>,
+    // Plaintext of the current segment
+    segment: Vec<u8>,
+    // Header or sealed segment waiting for the inner writer, of which `written` bytes are done
+    sealed: Vec<u8>,
+    written: usize,
+    // Set once the last segment is queued, after that only the output buffer is left to write
+    last_sealed: bool,
+}
+
+impl<W: Write> EncryptingWriter<W> {
+    // The nonce prefix must be unique per stream under the same key. The stream header is written
+    // together with the first segment.
+    pub fn new(inner: W, key: &[u8], nonce_prefix: &[u8; 7]) -> io::Result<Self> {
+        let encryptor = StreamEncryptor::new(key, nonce_prefix).map_err(io_error)?;
+        let mut sealed = Vec::with_capacity(SEALED_SEGMENT_LEN);
+        sealed.extend_from_slice(nonce_prefix);
+        let segment = Vec::with_capacity(SEALED_SEGMENT_LEN);
+        Ok(EncryptingWriter { inner, encryptor: Some(encryptor), segment, sealed, written: 0, last_sealed: false })
+    }
+
+    // Seals the last segment, writes out everything and flushes the inner writer. If the inner
+    // writer fails, nothing is lost and try_finish can be called again.
+    pub fn try_finish(&mut self) -> io::Result<()> {
+        self.write_sealed()?;
+        if !self.last_sealed {
+            let encryptor = self.encryptor.take().ok_or_else(finished)?;
+            let tag = encryptor.encrypt_last_in_place(&[], &mut self.segment).map_err(io_error)?;
+            self.queue_segment(&tag);
+            self.last_sealed = true;
+        }
+        self.write_sealed()?;
+        self.inner.flush()
+    }
+
+    // Same as try_finish, but returns the inner writer
+    pub fn finish(mut self) -> io::Result<W> {
+        self.try_finish()?;
+        Ok(self.inner)
+    }
+
+    pub fn get_ref(&self) -> &W {
+        &self.inner
+    }
+
+    // Moves the encrypted segment and its tag to the output buffer, which has to be empty
+    fn queue_segment(&mut self, tag: &[u8]) {
+        core::mem::swap(&mut self.segment, &mut self.sealed);
+        self.sealed.extend_from_slice(tag);
+        self.segment.clear();
+        self.written = 0;
+    }
+
+    // Hands the buffered sealed bytes to the inner writer, keeping track of how far it got
+    fn write_sealed(&mut self) -> io::Result<()> {
+        while self.written < self.sealed.len() {
+            match self.inner.write(&self.sealed[self.written..]) {
+                Ok(0) => return Err(io::ErrorKind::WriteZero.into()),
+                Ok(n) => self.written += n,
+                Err(ref e) if e.kind() == io::ErrorKind::Interrupted => {}
+                Err(e) => return Err(e),
+            }
+        }
+        self.sealed.clear();
+        self.written = 0;
+        Ok(())
+    }
+}
+
+fn finished() -> io::Error {
+    io::Error::other("stream already finished")
+}
+
+impl<W: Write> Write for EncryptingWriter<W> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        if buf.is_empty() {
+            return Ok(0);
+        }
+        if self.last_sealed {
+            return Err(finished());
+        }
+        self.write_sealed()?;
+        // A full segment is only sealed once more data follows, it might be the last one
+        if self.segment.len() == StreamEncryptor::SEGMENT_LEN {
+            let encryptor = self.encryptor.as_mut().ok_or_else(finished)?;
+            let tag = encryptor.encrypt_next_in_place(&[], &mut self.segment).map_err(io_error)?;
+            self.queue_segment(&tag);
+            // If this fails the segment stays queued and goes out with the next call
+            self.write_sealed()?;
+        }
+        let n = buf.len().min(StreamEncryptor::SEGMENT_LEN - self.segment.len());
+        self.segment.extend_from_slice(&buf[..n]);
+        Ok(n)
+    }
+
+    // Writes out sealed segments and flushes the inner writer. A partial segment cannot be written
+    // before finish.
+    fn flush(&mut self) -> io::Result<()> {
+        self.write_sealed()?;
+        self.inner.flush()
+    }
+}
+
+// Decrypts and authenticates a stream written by EncryptingWriter. Every segment is checked
+// before any of its plaintext is returned. A tampered or truncated stream gives an InvalidData
+// error, on this and every later read.
+pub struct DecryptingReader<R: Read> {
+    inner: R,
+    decryptor: Option<StreamDecryptor>,
+    failed: bool,
+    // Sealed bytes read ahead, one more than a segment to tell whether it is the last one
+    sealed: Vec<u8>,
+    plaintext: Vec<u8>,
+    pos: usize,
+}
+
+impl<R: Read> DecryptingReader<R> {
+    // Reads the stream header
+    pub fn new(mut inner: R, key: &[u8]) -> io::Result<Self> {
+        let mut nonce_prefix = [0u8; StreamEncryptor::NONCE_PREFIX_LEN];
+        inner.read_exact(&mut nonce_prefix)?;
+        let decryptor = StreamDecryptor::new(key, &nonce_prefix).map_err(io_error)?;
+        Ok(DecryptingReader {
+            inner,
+            decryptor: Some(decryptor),
+            failed: false,
+            sealed: Vec::with_capacity(SEALED_SEGMENT_LEN + 1),
+            plaintext: Vec::new(),
+            pos: 0,
+        })
+    }
+
+    pub fn into_inner(self) -> R {
+        self.inner
+    }
+
+    fn next_segment(&mut self) -> io::Result<()> {
+        if self.failed {
+            return Err(io_error(FeistelError::AuthenticationFailed));
+        }
+        let decryptor = match self.decryptor.as_mut() {
+            Some(decryptor) => decryptor,
+            None => return Ok(()),
+        };
+        let mut len = self.sealed.len();
+        self.sealed.resize(SEALED_SEGMENT_LEN + 1, 0);
+        let result = read_full(&mut self.inner, &mut self.sealed, &mut len);
+        // Keep the bytes read before an error, the next read continues from there
+        self.sealed.truncate(len);
+        result?;
+        let last = len <= SEALED_SEGMENT_LEN;
+        if len < FeistelAead::TAG_LEN {
+            self.failed = true;
+            return Err(io_error(FeistelError::AuthenticationFailed));
+        }
+        let segment_len = len.min(SEALED_SEGMENT_LEN);
+        let mut plaintext = core::mem::take(&mut self.plaintext);
+        plaintext.clear();
+        plaintext.extend_from_slice(&self.sealed[..segment_len - FeistelAead::TAG_LEN]);
+        let tag = &self.sealed[segment_len - FeistelAead::TAG_LEN..segment_len];
+        let result = if last {
+            let decryptor = self.decryptor.take().ok_or_else(finished)?;
+            decryptor.decrypt_last_in_place(&[], &mut plaintext, tag)
+        } else {
+            decryptor.decrypt_next_in_place(&[], &mut plaintext, tag)
+        };
+        if let Err(err) = result {
+            // Later segments can never verify, so the stream stays broken
+            self.failed = true;
+            return Err(io_error(err));
+        }
+        self.sealed.drain(..segment_len);
+        self.plaintext = plaintext;
+        self.pos = 0;
+        Ok(())
+    }
+}
+
+impl<R: Read> Read for DecryptingReader<R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        if self.pos == self.plaintext.len() {
+            self.plaintext.clear();
+            self.pos = 0;
+            self.next_segment()?;
+        }
+        let n = buf.len().min(self.plaintext.len() - self.pos);
+        buf[..n].copy_from_slice(&self.plaintext[self.pos..self.pos + n]);
+        self.pos += n;
+        Ok(n)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const KEY: &[u8] = b"backup key";
+
+    fn encrypt(data: &[u8]) -> Vec<u8> {
+        let mut writer = EncryptingWriter::new(Vec::new(), KEY, b"backup1").unwrap();
+        io::copy(&mut &data[..], &mut writer).unwrap();
+        writer.finish().unwrap()
+    }
+
+    fn decrypt(sealed: &[u8]) -> io::Result<Vec<u8>> {
+        let mut reader = DecryptingReader::new(sealed, KEY)?;
+        let mut plaintext = Vec::new();
+        reader.read_to_end(&mut plaintext)?;
+        Ok(plaintext)
+    }
+
+    #[test]
+    fn io_round_trip() {
+        let segment = StreamEncryptor::SEGMENT_LEN;
+        for &len in &[0, 1000, segment, segment + 17] {
+            let data: Vec<u8> = (0..len).map(|i| (i % 251) as u8).collect();
+            let sealed = encrypt(&data);
+            let segments = len.div_ceil(segment).max(1);
+            assert_eq!(sealed.len(), 7 + len + segments * FeistelAead::TAG_LEN);
+            assert_eq!(decrypt(&sealed).unwrap(), data);
+        }
+    }
+
+    // Accepts at most 1000 bytes per call and fails once with WouldBlock
+    struct FlakyWriter {
+        written: Vec<u8>,
+        calls: usize,
+        fail_at: usize,
+    }
+
+    impl Write for FlakyWriter {
+        fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+            self.calls += 1;
+            if self.calls == self.fail_at {
+                return Err(io::ErrorKind::WouldBlock.into());
+            }
+            let n = buf.len().min(1000);
+            self.written.extend_from_slice(&buf[..n]);
+            Ok(n)
+        }
+
+        fn flush(&mut self) -> io::Result<()> {
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn io_write_can_be_retried_after_errors() {
+        let data: Vec<u8> = (0..StreamEncryptor::SEGMENT_LEN + 100).map(|i| i as u8).collect();
+        // Fail while writing the header and in the middle of the first sealed segment
+        for &fail_at in &[1, 3] {
+            let flaky = FlakyWriter { written: Vec::new(), calls: 0, fail_at };
+            let mut writer = EncryptingWriter::new(flaky, KEY, b"backup1").unwrap();
+            let (mut rest, mut failures) = (&data[..], 0);
+            while !rest.is_empty() {
+                match writer.write(rest) {
+                    Ok(n) => rest = &rest[n..],
+                    Err(e) if e.kind() == io::ErrorKind::WouldBlock => failures += 1,
+                    Err(e) => panic!("{}", e),
+                }
+            }
+            assert_eq!(failures, 1);
+            let sealed = writer.finish().unwrap().written;
+            assert_eq!(decrypt(&sealed).unwrap(), data);
+        }
+    }
+
+    #[test]
+    fn io_finish_can_be_retried_after_errors() {
+        let data = vec![7u8; 1010];
+        // Fail before the last segment went out, and in the middle of it
+        for &fail_at in &[2, 3] {
+            let flaky = FlakyWriter { written: Vec::new(), calls: 0, fail_at };
+            let mut writer = EncryptingWriter::new(flaky, KEY, b"backup1").unwrap();
+            writer.write_all(&data).unwrap();
+            assert_eq!(writer.try_finish().unwrap_err().kind(), io::ErrorKind::WouldBlock);
+            writer.try_finish().unwrap();
+            let sealed = writer.finish().unwrap().written;
+            assert_eq!(decrypt(&sealed).unwrap(), data);
+        }
+    }
+
+    // Returns at most 1000 bytes per call and fails once with WouldBlock
+    struct FlakyReader<'a> {
+        data: &'a [u8],
+        calls: usize,
+        fail_at: usize,
+    }
+
+    impl Read for FlakyReader<'_> {
+        fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+            self.calls += 1;
+            if self.calls == self.fail_at {
+                return Err(io::ErrorKind::WouldBlock.into());
+            }
+            let n = buf.len().min(self.data.len()).min(1000);
+            buf[..n].copy_from_slice(&self.data[..n]);
+            self.data = &self.data[n..];
+            Ok(n)
+        }
+    }
+
+    #[test]
+    fn io_read_can_be_retried_after_errors() {
+        let data: Vec<u8> = (0..StreamEncryptor::SEGMENT_LEN + 100).map(|i| i as u8).collect();
+        let sealed = encrypt(&data);
+        // Fail right away, and after part of the first segment was read
+        for &fail_at in &[2, 3] {
+            let flaky = FlakyReader { data: &sealed, calls: 0, fail_at };
+            let mut reader = DecryptingReader::new(flaky, KEY).unwrap();
+            let (mut plaintext, mut failures) = (Vec::new(), 0);
+            let mut buf = [0u8; 4096];
+            loop {
+                match reader.read(&mut buf) {
+                    Ok(0) => break,
+                    Ok(n) => plaintext.extend_from_slice(&buf[..n]),
+                    Err(e) if e.kind() == io::ErrorKind::WouldBlock => failures += 1,
+                    Err(e) => panic!("{}", e),
+                }
+            }
+            assert_eq!(failures, 1);
+            assert_eq!(plaintext, data);
+        }
+    }
+
+    #[test]
+    fn io_rejects_tampering_and_truncation() {
+        let data = vec![42u8; StreamEncryptor::SEGMENT_LEN + 100];
+        let sealed = encrypt(&data);
+        let mut tampered = sealed.clone();
+        tampered[10] ^= 1;
+        assert_eq!(decrypt(&tampered).unwrap_err().kind(), io::ErrorKind::InvalidData);
+        // Cut off right after the first segment, and in the middle of the last one
+        let first_segment_end = 7 + SEALED_SEGMENT_LEN;
+        assert_eq!(decrypt(&sealed[..first_segment_end]).unwrap_err().kind(), io::ErrorKind::InvalidData);
+        assert_eq!(decrypt(&sealed[..sealed.len() - 1]).unwrap_err().kind(), io::ErrorKind::InvalidData);
+        assert_eq!(decrypt(&sealed[..7]).unwrap_err().kind(), io::ErrorKind::InvalidData);
+        assert_eq!(decrypt(&sealed[..3]).unwrap_err().kind(), io::ErrorKind::UnexpectedEof);
+
+        let mut reader = DecryptingReader::new(&tampered[..], KEY).unwrap();
+        let mut buf = [0u8; 16];
+        assert!(reader.read(&mut buf).is_err());
+        assert!(reader.read(&mut buf).is_err());
+    }
+}
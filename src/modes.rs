@@ -0,0 +1,587 @@
+// Block modes of operation over a fixed block size cipher such as Feistel64 or Feistel128.
+//
+// Every mode is a small state machine that keeps its chaining value between calls, so data can be
+// processed in pieces. ECB and CBC work on whole blocks, CFB, OFB and CTR on any length.
+// Use one instance per message and direction, and never reuse an IV or nonce under the same key.
+// Any cipher implementing the RustCrypto BlockEncrypt (and BlockDecrypt for ECB/CBC) traits works.
+
+use cipher::generic_array::GenericArray;
+use cipher::{Block, BlockDecrypt, BlockEncrypt, BlockSizeUser, Unsigned};
+
+use crate::error::FeistelError;
+
+fn block_size<C: BlockSizeUser>() -> usize {
+    C::BlockSize::USIZE
+}
+
+fn check_aligned<C: BlockSizeUser>(data: &[u8]) -> Result<(), FeistelError> {
+    if !data.len().is_multiple_of(block_size::<C>()) {
+        return Err(FeistelError::UnalignedLength { len: data.len(), block_size: block_size::<C>() });
+    }
+    Ok(())
+}
+
+fn iv_block<C: BlockSizeUser>(iv: &[u8]) -> Result<Block<C>, FeistelError> {
+    if iv.len() != block_size::<C>() {
+        return Err(FeistelError::InvalidIvLength { len: iv.len() });
+    }
+    Ok(GenericArray::clone_from_slice(iv))
+}
+
+fn xor(target: &mut [u8], other: &[u8]) {
+    for (t, o) in target.iter_mut().zip(other) {
+        *t ^= o;
+    }
+}
+
+// Padding schemes for ECB and CBC. The padding is always at least one byte, so a message that
+// is already block aligned gets a whole block of padding.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Padding {
+    // n bytes of value n
+    Pkcs7,
+    // 0x80 followed by zeros, ISO/IEC 7816-4
+    Iso7816,
+    // Zeros followed by a last byte holding the padding length, ANSI X9.23
+    AnsiX923,
+}
+
+impl Padding {
+    // Pads msg_len bytes at the start of buf and returns the padded length
+    pub fn pad(self, buf: &mut [u8], msg_len: usize, block_size: usize) -> Result<usize, FeistelError> {
+        check_block_size(block_size)?;
+        let padded_len = (msg_len / block_size + 1) * block_size;
+        if buf.len() < padded_len {
+            return Err(FeistelError::BufferTooSmall { len: buf.len(), needed: padded_len });
+        }
+        let pad_len = padded_len - msg_len;
+        let padding = &mut buf[msg_len..padded_len];
+        match self {
+            Padding::Pkcs7 => padding.fill(pad_len as u8),
+            Padding::Iso7816 => {
+                padding.fill(0);
+                padding[0] = 0x80;
+            }
+            Padding::AnsiX923 => {
+                padding.fill(0);
+                padding[pad_len - 1] = pad_len as u8;
+            }
+        }
+        Ok(padded_len)
+    }
+
+    // Returns the message length of padded data. Only the last block is inspected, and its bytes
+    // are checked without data dependent branches so timing does not reveal why it was rejected.
+    pub fn unpad(self, data: &[u8], block_size: usize) -> Result<usize, FeistelError> {
+        check_block_size(block_size)?;
+        if data.is_empty() || !data.len().is_multiple_of(block_size) {
+            return Err(FeistelError::InvalidPadding);
+        }
+        let last = &data[data.len() - block_size..];
+        let (pad_len, bad) = match self {
+            Padding::Pkcs7 | Padding::AnsiX923 => {
+                let pad_len = usize::from(last[block_size - 1]);
+                let mut bad = ct_is_zero(pad_len) | !ct_lt(pad_len, block_size + 1);
+                for (i, &byte) in last.iter().rev().enumerate() {
+                    let expected = match self {
+                        Padding::AnsiX923 if i > 0 => 0,
+                        _ => pad_len as u8,
+                    };
+                    bad |= ct_lt(i, pad_len) & (byte ^ expected);
+                }
+                (pad_len, bad)
+            }
+            Padding::Iso7816 => {
+                let (mut pad_len, mut bad, mut searching) = (0, 0, 0xff);
+                for (i, &byte) in last.iter().rev().enumerate() {
+                    let marker = ct_is_zero(usize::from(byte ^ 0x80));
+                    bad |= searching & !marker & byte;
+                    pad_len += usize::from(searching & marker & 1) * (i + 1);
+                    searching &= !marker;
+                }
+                (pad_len, bad | searching)
+            }
+        };
+        if bad != 0 {
+            return Err(FeistelError::InvalidPadding);
+        }
+        Ok(data.len() - pad_len)
+    }
+}
+
+// The padding length has to fit into one byte
+fn check_block_size(block_size: usize) -> Result<(), FeistelError> {
+    if !(1..=255).contains(&block_size) {
+        return Err(FeistelError::InvalidBlockSize { block_size });
+    }
+    Ok(())
+}
+
+// 0xff if a < b, else 0. Both must be below usize::MAX / 2.
+fn ct_lt(a: usize, b: usize) -> u8 {
+    0u8.wrapping_sub((a.wrapping_sub(b) >> (usize::BITS - 1)) as u8)
+}
+
+// 0xff if a == 0, else 0. a must be below usize::MAX / 2.
+fn ct_is_zero(a: usize) -> u8 {
+    ct_lt(a, 1)
+}
+
+// Ciphertext stealing variants from the SP 800-38A addendum. They differ only in the order of
+// the last two ciphertext blocks: CS1 keeps the CBC order, CS3 always swaps them and CS2 swaps
+// them only when the last block is partial, which makes it identical to plain CBC on aligned data.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum CtsVariant {
+    Cs1,
+    Cs2,
+    Cs3,
+}
+
+impl CtsVariant {
+    fn swapped(self, last_len: usize, block_size: usize) -> bool {
+        match self {
+            CtsVariant::Cs1 => false,
+            CtsVariant::Cs2 => last_len != block_size,
+            CtsVariant::Cs3 => true,
+        }
+    }
+}
+
+// Electronic codebook: every block is encrypted on its own. Equal blocks give equal ciphertext,
+// so this is only suitable for single blocks or random data.
+pub struct Ecb<C> {
+    cipher: C,
+}
+
+impl<C: BlockEncrypt + BlockDecrypt> Ecb<C> {
+    pub fn new(cipher: C) -> Self {
+        Ecb { cipher }
+    }
+
+    pub fn encrypt_blocks(&self, data: &mut [u8]) -> Result<(), FeistelError> {
+        check_aligned::<C>(data)?;
+        for chunk in data.chunks_exact_mut(block_size::<C>()) {
+            self.cipher.encrypt_block(GenericArray::from_mut_slice(chunk));
+        }
+        Ok(())
+    }
+
+    pub fn decrypt_blocks(&self, data: &mut [u8]) -> Result<(), FeistelError> {
+        check_aligned::<C>(data)?;
+        for chunk in data.chunks_exact_mut(block_size::<C>()) {
+            self.cipher.decrypt_block(GenericArray::from_mut_slice(chunk));
+        }
+        Ok(())
+    }
+
+    // Pads the msg_len bytes at the start of buf and encrypts them, returns the ciphertext length
+    pub fn encrypt_padded(&self, buf: &mut [u8], msg_len: usize, padding: Padding) -> Result<usize, FeistelError> {
+        let len = padding.pad(buf, msg_len, block_size::<C>())?;
+        self.encrypt_blocks(&mut buf[..len])?;
+        Ok(len)
+    }
+
+    // Decrypts data and returns the length of the message at its start
+    pub fn decrypt_padded(&self, data: &mut [u8], padding: Padding) -> Result<usize, FeistelError> {
+        self.decrypt_blocks(data).map_err(|_| FeistelError::InvalidPadding)?;
+        padding.unpad(data, block_size::<C>())
+    }
+}
+
+// Cipher block chaining: C[i] = E(P[i] ⊕ C[i-1]) with C[-1] = IV. The IV must be unpredictable.
+pub struct Cbc<C: BlockSizeUser> {
+    cipher: C,
+    chain: Block<C>,
+}
+
+impl<C: BlockEncrypt + BlockDecrypt> Cbc<C> {
+    pub fn new(cipher: C, iv: &[u8]) -> Result<Self, FeistelError> {
+        Ok(Cbc { cipher, chain: iv_block::<C>(iv)? })
+    }
+
+    pub fn encrypt_blocks(&mut self, data: &mut [u8]) -> Result<(), FeistelError> {
+        check_aligned::<C>(data)?;
+        for chunk in data.chunks_exact_mut(block_size::<C>()) {
+            xor(chunk, &self.chain);
+            self.cipher.encrypt_block(GenericArray::from_mut_slice(chunk));
+            self.chain.copy_from_slice(chunk);
+        }
+        Ok(())
+    }
+
+    pub fn decrypt_blocks(&mut self, data: &mut [u8]) -> Result<(), FeistelError> {
+        check_aligned::<C>(data)?;
+        for chunk in data.chunks_exact_mut(block_size::<C>()) {
+            let ciphertext = Block::<C>::clone_from_slice(chunk);
+            self.cipher.decrypt_block(GenericArray::from_mut_slice(chunk));
+            xor(chunk, &self.chain);
+            self.chain = ciphertext;
+        }
+        Ok(())
+    }
+
+    // Pads the msg_len bytes at the start of buf and encrypts them, returns the ciphertext length
+    pub fn encrypt_padded(&mut self, buf: &mut [u8], msg_len: usize, padding: Padding) -> Result<usize, FeistelError> {
+        let len = padding.pad(buf, msg_len, block_size::<C>())?;
+        self.encrypt_blocks(&mut buf[..len])?;
+        Ok(len)
+    }
+
+    // Decrypts data and returns the length of the message at its start
+    pub fn decrypt_padded(&mut self, data: &mut [u8], padding: Padding) -> Result<usize, FeistelError> {
+        self.decrypt_blocks(data).map_err(|_| FeistelError::InvalidPadding)?;
+        padding.unpad(data, block_size::<C>())
+    }
+
+    // Encrypts data of at least one block without expanding it, by stealing ciphertext from
+    // the second to last block to fill up a partial last block
+    pub fn encrypt_cts(&mut self, data: &mut [u8], variant: CtsVariant) -> Result<(), FeistelError> {
+        let bs = block_size::<C>();
+        if data.len() <= bs {
+            return self.encrypt_blocks(data).map_err(|_| FeistelError::BlockTooShort { len: data.len() });
+        }
+        let last_len = data.len() - (data.len() - 1) / bs * bs;
+        let tail_start = data.len() - last_len - bs;
+        self.encrypt_blocks(&mut data[..tail_start + bs])?;
+        // C[n-1] is now in place, C[n] = E((P[n] || 0) ⊕ C[n-1])
+        let mut last = self.chain.clone();
+        xor(&mut last[..last_len], &data[tail_start + bs..]);
+        self.cipher.encrypt_block(&mut last);
+        self.chain = last.clone();
+        let tail = &mut data[tail_start..];
+        if variant.swapped(last_len, bs) {
+            let penultimate = Block::<C>::clone_from_slice(&tail[..bs]);
+            tail[..bs].copy_from_slice(&last);
+            tail[bs..].copy_from_slice(&penultimate[..last_len]);
+        } else {
+            // The first last_len bytes of C[n-1] stay in place, followed by C[n]
+            tail[last_len..].copy_from_slice(&last);
+        }
+        Ok(())
+    }
+
+    pub fn decrypt_cts(&mut self, data: &mut [u8], variant: CtsVariant) -> Result<(), FeistelError> {
+        let bs = block_size::<C>();
+        if data.len() <= bs {
+            return self.decrypt_blocks(data).map_err(|_| FeistelError::BlockTooShort { len: data.len() });
+        }
+        let last_len = data.len() - (data.len() - 1) / bs * bs;
+        let tail_start = data.len() - last_len - bs;
+        self.decrypt_blocks(&mut data[..tail_start])?;
+        let tail = &mut data[tail_start..];
+        let (stolen, last) = if variant.swapped(last_len, bs) {
+            let (last, stolen) = tail.split_at(bs);
+            (stolen, Block::<C>::clone_from_slice(last))
+        } else {
+            let (stolen, last) = tail.split_at(last_len);
+            (stolen, Block::<C>::clone_from_slice(last))
+        };
+        // D(C[n]) = (P[n] || 0) ⊕ C[n-1], which recovers both P[n] and the stolen part of C[n-1]
+        let mut z = last.clone();
+        self.cipher.decrypt_block(&mut z);
+        let mut penultimate = z.clone();
+        penultimate[..last_len].copy_from_slice(stolen);
+        xor(&mut z[..last_len], stolen);
+        tail[bs..].copy_from_slice(&z[..last_len]);
+        tail[..bs].copy_from_slice(&penultimate);
+        self.decrypt_blocks(&mut tail[..bs])?;
+        self.chain = last;
+        Ok(())
+    }
+}
+
+// Cipher feedback with full block segments: C[i] = P[i] ⊕ E(C[i-1]). Works on any length,
+// a partial last block just uses part of the keystream block.
+pub struct Cfb<C: BlockSizeUser> {
+    cipher: C,
+    // Ciphertext of the current segment, completed byte by byte
+    feedback: Block<C>,
+    keystream: Block<C>,
+    pos: usize,
+}
+
+impl<C: BlockEncrypt> Cfb<C> {
+    pub fn new(cipher: C, iv: &[u8]) -> Result<Self, FeistelError> {
+        let feedback = iv_block::<C>(iv)?;
+        Ok(Cfb { cipher, feedback, keystream: Block::<C>::default(), pos: block_size::<C>() })
+    }
+
+    pub fn encrypt(&mut self, data: &mut [u8]) {
+        for byte in data {
+            let k = self.next_keystream_byte();
+            *byte ^= k;
+            self.feedback[self.pos - 1] = *byte;
+        }
+    }
+
+    pub fn decrypt(&mut self, data: &mut [u8]) {
+        for byte in data {
+            let k = self.next_keystream_byte();
+            self.feedback[self.pos - 1] = *byte;
+            *byte ^= k;
+        }
+    }
+
+    fn next_keystream_byte(&mut self) -> u8 {
+        if self.pos == block_size::<C>() {
+            self.keystream = self.feedback.clone();
+            self.cipher.encrypt_block(&mut self.keystream);
+            self.pos = 0;
+        }
+        self.pos += 1;
+        self.keystream[self.pos - 1]
+    }
+}
+
+// Output feedback: the keystream is E(IV), E(E(IV)), .. and gets XORed onto the data.
+// Encryption and decryption are the same operation.
+pub struct Ofb<C: BlockSizeUser> {
+    cipher: C,
+    keystream: Block<C>,
+    pos: usize,
+}
+
+impl<C: BlockEncrypt> Ofb<C> {
+    pub fn new(cipher: C, iv: &[u8]) -> Result<Self, FeistelError> {
+        Ok(Ofb { cipher, keystream: iv_block::<C>(iv)?, pos: block_size::<C>() })
+    }
+
+    pub fn apply_keystream(&mut self, data: &mut [u8]) {
+        for byte in data {
+            if self.pos == block_size::<C>() {
+                self.cipher.encrypt_block(&mut self.keystream);
+                self.pos = 0;
+            }
+            *byte ^= self.keystream[self.pos];
+            self.pos += 1;
+        }
+    }
+}
+
+// Counter mode: the keystream is E(nonce || counter) for counter = 0, 1, .. with the counter big
+// endian in the bytes after the nonce. The nonce has to be shorter than a block; whatever is left
+// bounds how much data one nonce can encrypt. Encryption and decryption are the same operation.
+pub struct Ctr<C: BlockSizeUser> {
+    cipher: C,
+    counter: Block<C>,
+    nonce_len: usize,
+    keystream: Block<C>,
+    pos: usize,
+    exhausted: bool,
+}
+
+impl<C: BlockEncrypt> Ctr<C> {
+    pub fn new(cipher: C, nonce: &[u8]) -> Result<Self, FeistelError> {
+        if nonce.len() >= block_size::<C>() {
+            return Err(FeistelError::InvalidIvLength { len: nonce.len() });
+        }
+        let mut counter = Block::<C>::default();
+        counter[..nonce.len()].copy_from_slice(nonce);
+        let keystream = Block::<C>::default();
+        Ok(Ctr { cipher, counter, nonce_len: nonce.len(), keystream, pos: block_size::<C>(), exhausted: false })
+    }
+
+    // Fails without touching the data if it would run past the last counter value
+    pub fn apply_keystream(&mut self, data: &mut [u8]) -> Result<(), FeistelError> {
+        let available = block_size::<C>() - self.pos;
+        if data.len() > available && self.exhausted {
+            return Err(FeistelError::KeystreamExhausted);
+        }
+        let counter_bits = 8 * (block_size::<C>() - self.nonce_len) as u32;
+        let blocks_needed = (data.len().saturating_sub(available)).div_ceil(block_size::<C>()) as u128;
+        if counter_bits < 128 && self.counter_value() + blocks_needed > 1u128 << counter_bits {
+            return Err(FeistelError::KeystreamExhausted);
+        }
+        for byte in data {
+            if self.pos == block_size::<C>() {
+                self.keystream = self.counter.clone();
+                self.cipher.encrypt_block(&mut self.keystream);
+                self.increment();
+                self.pos = 0;
+            }
+            *byte ^= self.keystream[self.pos];
+            self.pos += 1;
+        }
+        Ok(())
+    }
+
+    fn counter_value(&self) -> u128 {
+        if self.exhausted {
+            return u128::MAX;
+        }
+        self.counter[self.nonce_len..].iter().fold(0, |acc, &b| acc.saturating_mul(256).saturating_add(b.into()))
+    }
+
+    fn increment(&mut self) {
+        for b in self.counter[self.nonce_len..].iter_mut().rev() {
+            *b = b.wrapping_add(1);
+            if *b != 0 {
+                return;
+            }
+        }
+        // The counter wrapped around, every keystream block has been used
+        self.exhausted = true;
+    }
+}
+
+#[cfg(all(test, feature = "std"))]
+mod tests {
+    use super::*;
+    use crate::{Feistel128, Feistel64};
+    use cipher::{KeyInit, KeyIvInit, StreamCipher};
+
+    const MESSAGE: &[u8; 48] = b"three blocks of sixteen bytes make 48 in total!!";
+
+    fn feistel() -> Feistel128 {
+        Feistel128::new(&[9u8; 32].into())
+    }
+
+    #[test]
+    fn ecb_and_cbc_round_trip() {
+        let mut data = *MESSAGE;
+        let ecb = Ecb::new(feistel());
+        ecb.encrypt_blocks(&mut data).unwrap();
+        assert_eq!(data[..16], {
+            let mut first = *GenericArray::from_slice(&MESSAGE[..16]);
+            feistel().encrypt_block(&mut first);
+            first
+        }[..]);
+        ecb.decrypt_blocks(&mut data).unwrap();
+        assert_eq!(&data, MESSAGE);
+
+        let iv = [3u8; 16];
+        Cbc::new(feistel(), &iv).unwrap().encrypt_blocks(&mut data).unwrap();
+        // Chaining state carries over between calls
+        let mut cbc = Cbc::new(feistel(), &iv).unwrap();
+        cbc.decrypt_blocks(&mut data[..16]).unwrap();
+        cbc.decrypt_blocks(&mut data[16..]).unwrap();
+        assert_eq!(&data, MESSAGE);
+        assert_eq!(ecb.encrypt_blocks(&mut data[..15]), Err(FeistelError::UnalignedLength { len: 15, block_size: 16 }));
+        assert_eq!(Cbc::new(feistel(), &iv[..8]).err().map(|_| ()), Some(()));
+    }
+
+    #[test]
+    fn padding_round_trip_and_rejection() {
+        for &padding in &[Padding::Pkcs7, Padding::Iso7816, Padding::AnsiX923] {
+            for msg_len in 0..=33 {
+                let mut buf = [0u8; 48];
+                buf[..msg_len].copy_from_slice(&MESSAGE[..msg_len]);
+                let len = Cbc::new(feistel(), &[7; 16]).unwrap().encrypt_padded(&mut buf, msg_len, padding).unwrap();
+                assert_eq!(len, (msg_len / 16 + 1) * 16);
+                let mut cbc = Cbc::new(feistel(), &[7; 16]).unwrap();
+                assert_eq!(cbc.decrypt_padded(&mut buf[..len], padding), Ok(msg_len));
+                assert_eq!(buf[..msg_len], MESSAGE[..msg_len]);
+            }
+        }
+        assert_eq!(Padding::Pkcs7.unpad(&[1, 2, 3, 3, 3, 3, 3, 3], 8), Ok(5));
+        assert_eq!(Padding::Pkcs7.unpad(&[1, 2, 3, 4, 5, 6, 3, 3], 8), Err(FeistelError::InvalidPadding));
+        assert_eq!(Padding::Pkcs7.unpad(&[9; 8], 8), Err(FeistelError::InvalidPadding));
+        assert_eq!(Padding::Pkcs7.unpad(&[8; 8], 8), Ok(0));
+        assert_eq!(Padding::Pkcs7.unpad(&[1, 2, 3, 4, 5, 6, 7, 0], 8), Err(FeistelError::InvalidPadding));
+        assert_eq!(Padding::Iso7816.unpad(&[1, 2, 3, 4, 0x80, 0, 0, 0], 8), Ok(4));
+        assert_eq!(Padding::Iso7816.unpad(&[1, 2, 3, 4, 0x80, 0, 1, 0], 8), Err(FeistelError::InvalidPadding));
+        assert_eq!(Padding::Iso7816.unpad(&[0; 8], 8), Err(FeistelError::InvalidPadding));
+        assert_eq!(Padding::AnsiX923.unpad(&[1, 2, 3, 4, 5, 0, 0, 3], 8), Ok(5));
+        assert_eq!(Padding::AnsiX923.unpad(&[1, 2, 3, 4, 5, 3, 3, 3], 8), Err(FeistelError::InvalidPadding));
+        assert_eq!(Padding::Pkcs7.unpad(&[1; 7], 8), Err(FeistelError::InvalidPadding));
+        assert_eq!(Padding::Pkcs7.pad(&mut [0; 16], 0, 0), Err(FeistelError::InvalidBlockSize { block_size: 0 }));
+        assert_eq!(Padding::Pkcs7.pad(&mut [0; 256], 0, 256), Err(FeistelError::InvalidBlockSize { block_size: 256 }));
+        assert_eq!(Padding::AnsiX923.unpad(&[1; 16], 0), Err(FeistelError::InvalidBlockSize { block_size: 0 }));
+        assert_eq!(Padding::Pkcs7.pad(&mut [0; 255], 0, 255), Ok(255));
+        assert_eq!(Padding::Pkcs7.unpad(&[255; 255], 255), Ok(0));
+        assert_eq!(
+            Padding::Pkcs7.pad(&mut [0; 16], 16, 16),
+            Err(FeistelError::BufferTooSmall { len: 16, needed: 32 })
+        );
+    }
+
+    #[test]
+    fn cts_matches_rfc3962() {
+        // Kerberos uses CBC-CS3 with AES and a zero IV
+        let key = *b"chicken teriyaki";
+        let cases: &[(usize, &str)] = &[
+            (17, "c6353568f2bf8cb4d8a580362da7ff7f97"),
+            (31, "fc00783e0efdb2c1d445d4c8eff7ed2297687268d6ecccc0c07b25e25ecfe5"),
+            (32, "39312523a78662d5be7fcbcc98ebf5a897687268d6ecccc0c07b25e25ecfe584"),
+        ];
+        let plaintext = b"I would like the General Gau's Chicken, please, and wonton soup.";
+        for &(len, expected) in cases {
+            let mut data = plaintext[..len].to_vec();
+            let aes = aes::Aes128::new(&key.into());
+            Cbc::new(aes.clone(), &[0; 16]).unwrap().encrypt_cts(&mut data, CtsVariant::Cs3).unwrap();
+            assert_eq!(pretty_hex::simple_hex(&data).replace(' ', ""), expected);
+            Cbc::new(aes, &[0; 16]).unwrap().decrypt_cts(&mut data, CtsVariant::Cs3).unwrap();
+            assert_eq!(data, &plaintext[..len]);
+        }
+    }
+
+    #[test]
+    fn cts_variants_round_trip() {
+        let iv = [1u8; 16];
+        for &variant in &[CtsVariant::Cs1, CtsVariant::Cs2, CtsVariant::Cs3] {
+            for len in 16..=MESSAGE.len() {
+                let mut data = MESSAGE[..len].to_vec();
+                Cbc::new(feistel(), &iv).unwrap().encrypt_cts(&mut data, variant).unwrap();
+                Cbc::new(feistel(), &iv).unwrap().decrypt_cts(&mut data, variant).unwrap();
+                assert_eq!(data, &MESSAGE[..len]);
+            }
+        }
+        let mut cbc = *MESSAGE;
+        Cbc::new(feistel(), &iv).unwrap().encrypt_blocks(&mut cbc).unwrap();
+        let mut cs1 = *MESSAGE;
+        Cbc::new(feistel(), &iv).unwrap().encrypt_cts(&mut cs1, CtsVariant::Cs1).unwrap();
+        let mut cs2 = *MESSAGE;
+        Cbc::new(feistel(), &iv).unwrap().encrypt_cts(&mut cs2, CtsVariant::Cs2).unwrap();
+        assert_eq!(cbc, cs1);
+        assert_eq!(cbc, cs2);
+        // CS1 and CS3 only differ in the order of the last two blocks
+        let mut cs1 = MESSAGE[..40].to_vec();
+        Cbc::new(feistel(), &iv).unwrap().encrypt_cts(&mut cs1, CtsVariant::Cs1).unwrap();
+        let mut cs3 = MESSAGE[..40].to_vec();
+        Cbc::new(feistel(), &iv).unwrap().encrypt_cts(&mut cs3, CtsVariant::Cs3).unwrap();
+        assert_eq!(cs1[..16], cs3[..16]);
+        assert_eq!(cs1[16..24], cs3[32..]);
+        assert_eq!(cs1[24..], cs3[16..32]);
+        let mut short = [0u8; 15];
+        let result = Cbc::new(feistel(), &iv).unwrap().encrypt_cts(&mut short, CtsVariant::Cs1);
+        assert_eq!(result, Err(FeistelError::BlockTooShort { len: 15 }));
+    }
+
+    #[test]
+    fn stream_modes_round_trip_any_length() {
+        let iv = [5u8; 16];
+        let mut data = MESSAGE[..45].to_vec();
+        let mut cfb = Cfb::new(feistel(), &iv).unwrap();
+        cfb.encrypt(&mut data[..7]);
+        cfb.encrypt(&mut data[7..]);
+        Cfb::new(feistel(), &iv).unwrap().decrypt(&mut data);
+        assert_eq!(data, &MESSAGE[..45]);
+
+        Ofb::new(feistel(), &iv).unwrap().apply_keystream(&mut data);
+        assert_ne!(data, &MESSAGE[..45]);
+        Ofb::new(feistel(), &iv).unwrap().apply_keystream(&mut data);
+        assert_eq!(data, &MESSAGE[..45]);
+    }
+
+    #[test]
+    fn ctr_matches_ctr_crate() {
+        let key = [1u8; 32];
+        let mut ours = MESSAGE.to_vec();
+        let mut theirs = MESSAGE.to_vec();
+        // Without a nonce the whole block is the counter, starting at zero
+        Ctr::new(Feistel64::new(&key.into()), &[]).unwrap().apply_keystream(&mut ours).unwrap();
+        ctr::Ctr64BE::<Feistel64>::new(&key.into(), &[0u8; 8].into()).apply_keystream(&mut theirs);
+        assert_eq!(ours, theirs);
+    }
+
+    #[test]
+    fn ctr_refuses_to_reuse_keystream() {
+        let mut ctr = Ctr::new(Feistel64::new(&[1u8; 32].into()), &[1, 2, 3, 4, 5, 6, 7]).unwrap();
+        let mut data = [0u8; 8 * 256];
+        assert!(ctr.apply_keystream(&mut data).is_ok());
+        assert_eq!(ctr.apply_keystream(&mut data[..1]), Err(FeistelError::KeystreamExhausted));
+        let mut ctr = Ctr::new(Feistel64::new(&[1u8; 32].into()), &[1, 2, 3, 4, 5, 6, 7]).unwrap();
+        assert_eq!(ctr.apply_keystream(&mut [0u8; 8 * 256 + 1]), Err(FeistelError::KeystreamExhausted));
+        assert_eq!(Ctr::new(Feistel64::new(&[1u8; 32].into()), &[0; 8]).err().map(|_| ()), Some(()));
+    }
+}
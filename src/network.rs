@@ -0,0 +1,187 @@
+// The Feistel network itself, independent of round function and key schedule.
+//
+// The network runs in place: the block is split into A = block[..n/2] and B = block[n/2..] and
+// every round XORs F(source) into the other half, alternating A ^= F(B) and B ^= F(A).
+// This is the same as the textbook L[i+1] = R[i], R[i+1] = L[i] ⊕ F(R[i]) without moving bytes
+// around. The output is R || L, so after an even number of rounds the halves get swapped once.
+//
+// `round(i, source, target)` has to XOR the output of round i into target.
+//
+// Unbalanced networks use a fixed target size t instead: every round XORs F(block[t..]) into
+// block[..t] and then rotates the block left by t bytes. This is invertible for every 0 < t < n.
+// Odd length blocks run the unbalanced network with t = n/2, so every byte gets combined.
+//
+// Split::Legacy runs the alternating network on odd length blocks as well. It only touches the
+// first min(len(A), len(B)) bytes of the target, which leaves the last byte of the block
+// unencrypted, and is kept to decrypt ciphertexts of the first versions only.
+
+#[cfg(feature = "alloc")]
+use crate::error::FeistelError;
+
+// How a block is divided into the half that gets updated (target) and the half F reads (source)
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum Split {
+    // Halves of n/2 and n - n/2 bytes. Odd lengths are the same as Unbalanced { target: n / 2 }.
+    #[default]
+    Balanced,
+    // `target` bytes are updated from the remaining n - target bytes every round.
+    // target < n/2 gives a source-heavy network, target > n/2 a target-heavy one.
+    Unbalanced { target: usize },
+    // The original layout of the crate, see feistel_decrypt_legacy. For odd lengths the
+    // last byte of the longer half is never combined with anything and shows up in the ciphertext,
+    // so only use this to decrypt old ciphertexts.
+    Legacy,
+}
+
+#[cfg(feature = "alloc")]
+impl Split {
+    pub(crate) fn check(&self, len: usize) -> Result<(), FeistelError> {
+        match *self {
+            Split::Unbalanced { target } if target == 0 || target >= len => {
+                Err(FeistelError::InvalidSplit { target, len })
+            }
+            _ => Ok(()),
+        }
+    }
+
+    // Like check, but also refuses to produce new legacy ciphertexts that leave a byte unencrypted.
+    // Decryption still accepts them, so old ciphertexts stay readable.
+    pub(crate) fn check_encrypt(&self, len: usize) -> Result<(), FeistelError> {
+        if *self == Split::Legacy && !len.is_multiple_of(2) {
+            return Err(FeistelError::InvalidSplit { target: len / 2, len });
+        }
+        self.check(len)
+    }
+}
+
+pub(crate) fn encrypt<R>(block: &mut [u8], rounds: u32, round: R)
+where R: FnMut(u32, &[u8], &mut [u8]) {
+    if !block.len().is_multiple_of(2) {
+        encrypt_unbalanced(block, block.len() / 2, rounds, round);
+    } else {
+        encrypt_alternating(block, rounds, round);
+    }
+}
+
+pub(crate) fn decrypt<R>(block: &mut [u8], rounds: u32, round: R)
+where R: FnMut(u32, &[u8], &mut [u8]) {
+    if !block.len().is_multiple_of(2) {
+        decrypt_unbalanced(block, block.len() / 2, rounds, round);
+    } else {
+        decrypt_alternating(block, rounds, round);
+    }
+}
+
+pub(crate) fn encrypt_alternating<R>(block: &mut [u8], rounds: u32, mut round: R)
+where R: FnMut(u32, &[u8], &mut [u8]) {
+    let split = block.len() / 2;
+    alternate(block, split, 0..rounds, |i, source, target| xor_round(i, source, target, &mut round));
+    if rounds.is_multiple_of(2) {
+        block.rotate_left(split);
+    }
+}
+
+pub(crate) fn decrypt_alternating<R>(block: &mut [u8], rounds: u32, mut round: R)
+where R: FnMut(u32, &[u8], &mut [u8]) {
+    let split = block.len() / 2;
+    if rounds.is_multiple_of(2) {
+        block.rotate_right(split);
+    }
+    // XOR is its own inverse, so decryption is just the rounds in reverse order
+    alternate(block, split, (0..rounds).rev(), |i, source, target| xor_round(i, source, target, &mut round));
+}
+
+fn xor_round<R>(i: u32, source: &[u8], target: &mut [u8], round: &mut R)
+where R: FnMut(u32, &[u8], &mut [u8]) {
+    let xor_len = target.len().min(source.len());
+    round(i, source, &mut target[..xor_len]);
+}
+
+// The round loop shared by all alternating networks: block[..split] is the target of even rounds,
+// block[split..] the target of odd rounds. It works on any element type, so the numeral string
+// modes (FF1, FF3-1) run on the same loop as the byte network.
+pub(crate) fn alternate<T, I, R>(block: &mut [T], split: usize, rounds: I, mut round: R)
+where I: Iterator<Item = u32>, R: FnMut(u32, &[T], &mut [T]) {
+    for i in rounds {
+        let (a, b) = block.split_at_mut(split);
+        let (source, target) = if i.is_multiple_of(2) { (&*b, a) } else { (&*a, b) };
+        round(i, source, target);
+    }
+}
+
+pub(crate) fn encrypt_unbalanced<R>(block: &mut [u8], target: usize, rounds: u32, mut round: R)
+where R: FnMut(u32, &[u8], &mut [u8]) {
+    for i in 0..rounds {
+        let (t, s) = block.split_at_mut(target);
+        round(i, s, t);
+        block.rotate_left(target);
+    }
+}
+
+pub(crate) fn decrypt_unbalanced<R>(block: &mut [u8], target: usize, rounds: u32, mut round: R)
+where R: FnMut(u32, &[u8], &mut [u8]) {
+    for i in (0..rounds).rev() {
+        block.rotate_right(target);
+        let (t, s) = block.split_at_mut(target);
+        round(i, s, t);
+    }
+}
+
+// Bit granular network over the first `bits` bits of `data`, most significant bit first.
+// The bit string is split into A = bits/2 and B = bits - bits/2 bits which alternate as target just
+// like the byte network, but F always covers the whole target half. The halves are not swapped at
+// the end. F sees the source half packed into bytes, left aligned and padded with zero bits.
+// `scratch` holds the packed halves and has to be at least bit_scratch_len(bits) bytes long, so
+// callers with small fixed bit lengths can keep it on the stack.
+#[cfg(feature = "alloc")]
+pub(crate) fn encrypt_bits<R>(data: &mut [u8], bits: usize, rounds: u32, scratch: &mut [u8], mut round: R)
+where R: FnMut(u32, &[u8], &mut [u8]) {
+    for i in 0..rounds {
+        apply_bit_round(data, bits, i, scratch, &mut round);
+    }
+}
+
+#[cfg(feature = "alloc")]
+pub(crate) fn decrypt_bits<R>(data: &mut [u8], bits: usize, rounds: u32, scratch: &mut [u8], mut round: R)
+where R: FnMut(u32, &[u8], &mut [u8]) {
+    for i in (0..rounds).rev() {
+        apply_bit_round(data, bits, i, scratch, &mut round);
+    }
+}
+
+#[cfg(feature = "alloc")]
+pub(crate) fn bit_scratch_len(bits: usize) -> usize {
+    2 * (bits - bits / 2).div_ceil(8)
+}
+
+#[cfg(feature = "alloc")]
+fn apply_bit_round<R>(data: &mut [u8], bits: usize, i: u32, scratch: &mut [u8], round: &mut R)
+where R: FnMut(u32, &[u8], &mut [u8]) {
+    let split = bits / 2;
+    let ((source_start, source_len), (target_start, target_len)) = if i.is_multiple_of(2) {
+        ((split, bits - split), (0, split))
+    } else {
+        ((0, split), (split, bits - split))
+    };
+    let (source, output) = scratch.split_at_mut(bit_scratch_len(bits) / 2);
+    let source = &mut source[..source_len.div_ceil(8)];
+    let output = &mut output[..target_len.div_ceil(8)];
+    source.iter_mut().for_each(|b| *b = 0);
+    output.iter_mut().for_each(|b| *b = 0);
+    for j in 0..source_len {
+        if bit(data, source_start + j) {
+            source[j / 8] |= 0x80 >> (j % 8);
+        }
+    }
+    round(i, source, output);
+    for j in 0..target_len {
+        if bit(output, j) {
+            data[(target_start + j) / 8] ^= 0x80 >> ((target_start + j) % 8);
+        }
+    }
+}
+
+#[cfg(feature = "alloc")]
+fn bit(data: &[u8], i: usize) -> bool {
+    data[i / 8] & (0x80 >> (i % 8)) != 0
+}
@@ -0,0 +1,101 @@
+// Helpers shared by the NIST format preserving encryption modes: AES as the PRF and conversions
+// between numeral strings (one u16 per digit, most significant first) and integers.
+//
+// Numbers are kept in a u128, so the modes only support domains where the larger half has at most
+// 2^96 values. That covers every practical use (e.g. 28 decimal digits per half).
+
+use aes::cipher::{generic_array::GenericArray, BlockEncrypt, KeyInit};
+use aes::{Aes128, Aes192, Aes256};
+
+use crate::error::FeistelError;
+
+pub(crate) const MAX_RADIX: u32 = 1 << 16;
+pub(crate) const MAX_HALF_DOMAIN: u128 = 1 << 96;
+// NIST requires radix^n >= 1,000,000
+pub(crate) const MIN_DOMAIN: u128 = 1_000_000;
+
+#[derive(Clone)]
+pub(crate) enum Aes {
+    Aes128(Aes128),
+    Aes192(Aes192),
+    Aes256(Aes256),
+}
+
+impl Aes {
+    pub(crate) fn new(key: &[u8]) -> Result<Self, FeistelError> {
+        let invalid = |_| FeistelError::InvalidKeyLength { len: key.len() };
+        Ok(match key.len() {
+            16 => Aes::Aes128(Aes128::new_from_slice(key).map_err(invalid)?),
+            24 => Aes::Aes192(Aes192::new_from_slice(key).map_err(invalid)?),
+            32 => Aes::Aes256(Aes256::new_from_slice(key).map_err(invalid)?),
+            len => return Err(FeistelError::InvalidKeyLength { len }),
+        })
+    }
+
+    pub(crate) fn encrypt(&self, block: &mut [u8; 16]) {
+        let block = GenericArray::from_mut_slice(block);
+        match self {
+            Aes::Aes128(aes) => aes.encrypt_block(block),
+            Aes::Aes192(aes) => aes.encrypt_block(block),
+            Aes::Aes256(aes) => aes.encrypt_block(block),
+        }
+    }
+}
+
+pub(crate) fn check_radix(radix: u32) -> Result<(), FeistelError> {
+    if !(2..=MAX_RADIX).contains(&radix) {
+        return Err(FeistelError::InvalidRadix { radix });
+    }
+    Ok(())
+}
+
+pub(crate) fn check_numerals(radix: u32, numerals: &[u16]) -> Result<(), FeistelError> {
+    match numerals.iter().find(|&&x| u32::from(x) >= radix) {
+        Some(&numeral) => Err(FeistelError::InvalidNumeral { numeral, radix }),
+        None => Ok(()),
+    }
+}
+
+// radix^m, or None if it does not fit into a u128
+pub(crate) fn pow(radix: u32, m: usize) -> Option<u128> {
+    (0..m).try_fold(1u128, |acc, _| acc.checked_mul(u128::from(radix)))
+}
+
+// NUM_radix(X), the number represented by a numeral string, most significant numeral first
+pub(crate) fn num(radix: u32, numerals: &[u16]) -> u128 {
+    numerals.iter().fold(0, |acc, &x| acc * u128::from(radix) + u128::from(x))
+}
+
+// STR_radix(x), writes x as out.len() numerals, most significant numeral first
+pub(crate) fn write_str(radix: u32, mut x: u128, out: &mut [u16]) {
+    for numeral in out.iter_mut().rev() {
+        *numeral = (x % u128::from(radix)) as u16;
+        x /= u128::from(radix);
+    }
+}
+
+// NUM_radix(REV(X)), the number represented by a numeral string, least significant numeral first
+pub(crate) fn num_rev(radix: u32, numerals: &[u16]) -> u128 {
+    numerals.iter().rev().fold(0, |acc, &x| acc * u128::from(radix) + u128::from(x))
+}
+
+// REV(STR_radix(x)), writes x as out.len() numerals, least significant numeral first
+pub(crate) fn write_str_rev(radix: u32, mut x: u128, out: &mut [u16]) {
+    for numeral in out.iter_mut() {
+        *numeral = (x % u128::from(radix)) as u16;
+        x /= u128::from(radix);
+    }
+}
+
+// Parsing of the NIST sample vectors, shared by the FF1 and FF3-1 tests
+#[cfg(all(test, feature = "std"))]
+pub(crate) mod test_helpers {
+    // Numerals written as base 36 digits
+    pub(crate) fn numerals(s: &str) -> Vec<u16> {
+        s.chars().map(|c| c.to_digit(36).unwrap() as u16).collect()
+    }
+
+    pub(crate) fn hex(s: &str) -> Vec<u8> {
+        (0..s.len()).step_by(2).map(|i| u8::from_str_radix(&s[i..i + 2], 16).unwrap()).collect()
+    }
+}
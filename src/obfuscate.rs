@@ -0,0 +1,161 @@
+// Keyed, reversible obfuscation of sequential IDs.
+//
+// IDs are encrypted as fixed width big endian integers with the byte network, in place on the stack,
+// so an obfuscated u32 is again a u32 and every call is allocation free. Optional base62 and base32
+// encodings give short strings for URLs.
+
+use alloc::string::String;
+
+use crate::cipher::FeistelCipher;
+use crate::error::FeistelError;
+use crate::round::{RoundFunction, Sha3Round};
+
+const ROUNDS: u32 = 8;
+const BASE62: &[u8; 62] = b"0123456789ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz";
+// Digits and lower case letters without i, l, o and u, which are easily confused
+const BASE32: &[u8; 32] = b"0123456789abcdefghjkmnpqrstvwxyz";
+
+pub struct IdObfuscator<F: RoundFunction = Sha3Round> {
+    cipher: FeistelCipher<F>,
+}
+
+impl IdObfuscator {
+    pub fn new(key: &[u8]) -> Result<Self, FeistelError> {
+        Ok(IdObfuscator { cipher: FeistelCipher::try_new(key, ROUNDS)? })
+    }
+}
+
+impl<F: RoundFunction> IdObfuscator<F> {
+    pub fn from_cipher(cipher: FeistelCipher<F>) -> Self {
+        IdObfuscator { cipher }
+    }
+
+    pub fn obfuscate_u32(&self, id: u32) -> u32 {
+        let mut block = id.to_be_bytes();
+        self.cipher.encrypt_in_place(&mut block);
+        u32::from_be_bytes(block)
+    }
+
+    pub fn reveal_u32(&self, id: u32) -> u32 {
+        let mut block = id.to_be_bytes();
+        self.cipher.decrypt_in_place(&mut block);
+        u32::from_be_bytes(block)
+    }
+
+    pub fn obfuscate_u64(&self, id: u64) -> u64 {
+        let mut block = id.to_be_bytes();
+        self.cipher.encrypt_in_place(&mut block);
+        u64::from_be_bytes(block)
+    }
+
+    pub fn reveal_u64(&self, id: u64) -> u64 {
+        let mut block = id.to_be_bytes();
+        self.cipher.decrypt_in_place(&mut block);
+        u64::from_be_bytes(block)
+    }
+
+    pub fn obfuscate_u128(&self, id: u128) -> u128 {
+        u128::from_be_bytes(self.obfuscate_uuid(id.to_be_bytes()))
+    }
+
+    pub fn reveal_u128(&self, id: u128) -> u128 {
+        u128::from_be_bytes(self.reveal_uuid(id.to_be_bytes()))
+    }
+
+    // UUIDs in their 16 byte binary form
+    pub fn obfuscate_uuid(&self, mut uuid: [u8; 16]) -> [u8; 16] {
+        self.cipher.encrypt_in_place(&mut uuid);
+        uuid
+    }
+
+    pub fn reveal_uuid(&self, mut uuid: [u8; 16]) -> [u8; 16] {
+        self.cipher.decrypt_in_place(&mut uuid);
+        uuid
+    }
+}
+
+// Shortest base62 representation of the value, "0" for zero.
+// Decoding only accepts this canonical form, so every ID has exactly one string (no leading zeros).
+pub fn encode_base62(value: u128) -> String {
+    encode(value, BASE62)
+}
+
+pub fn decode_base62(encoded: &str) -> Result<u128, FeistelError> {
+    decode(encoded, BASE62)
+}
+
+// Shortest base32 representation of the value in lower case, "0" for zero.
+// As for base62, decoding only accepts this canonical form: no leading zeros and no upper case.
+pub fn encode_base32(value: u128) -> String {
+    encode(value, BASE32)
+}
+
+pub fn decode_base32(encoded: &str) -> Result<u128, FeistelError> {
+    decode(encoded, BASE32)
+}
+
+fn encode(mut value: u128, alphabet: &[u8]) -> String {
+    let base = alphabet.len() as u128;
+    // 128 bits need at most 128 digits, even in base 2
+    let mut digits = [0u8; 128];
+    let mut start = digits.len();
+    loop {
+        start -= 1;
+        digits[start] = alphabet[(value % base) as usize];
+        value /= base;
+        if value == 0 {
+            break;
+        }
+    }
+    digits[start..].iter().map(|&d| d as char).collect()
+}
+
+fn decode(encoded: &str, alphabet: &[u8]) -> Result<u128, FeistelError> {
+    if encoded.is_empty() || (encoded.len() > 1 && encoded.as_bytes()[0] == alphabet[0]) {
+        return Err(FeistelError::InvalidEncoding);
+    }
+    encoded.bytes().try_fold(0u128, |acc, c| {
+        let digit = alphabet.iter().position(|&a| a == c).ok_or(FeistelError::InvalidEncoding)?;
+        acc.checked_mul(alphabet.len() as u128)
+            .and_then(|acc| acc.checked_add(digit as u128))
+            .ok_or(FeistelError::InvalidEncoding)
+    })
+}
+
+#[cfg(all(test, feature = "std"))]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn ids_round_trip() {
+        let obfuscator = IdObfuscator::new(b"secret").unwrap();
+        for id in 0u32..1000 {
+            let obfuscated = obfuscator.obfuscate_u32(id);
+            assert_eq!(obfuscator.reveal_u32(obfuscated), id);
+            assert_eq!(obfuscator.reveal_u64(obfuscator.obfuscate_u64(id.into())), u64::from(id));
+        }
+        assert_ne!(obfuscator.obfuscate_u64(1), 1);
+        let uuid = *b"\x6b\xa7\xb8\x10\x9d\xad\x11\xd1\x80\xb4\x00\xc0\x4f\xd4\x30\xc8";
+        assert_eq!(obfuscator.reveal_uuid(obfuscator.obfuscate_uuid(uuid)), uuid);
+        assert_eq!(obfuscator.reveal_u128(obfuscator.obfuscate_u128(u128::MAX)), u128::MAX);
+    }
+
+    #[test]
+    fn string_encodings() {
+        for &value in [0u128, 1, 61, 62, u64::MAX.into(), u128::MAX].iter() {
+            assert_eq!(decode_base62(&encode_base62(value)), Ok(value));
+            assert_eq!(decode_base32(&encode_base32(value)), Ok(value));
+        }
+        assert_eq!(encode_base62(62), "10");
+        assert_eq!(encode_base32(u32::MAX.into()), "3zzzzzz");
+        assert_eq!(decode_base32("0"), Ok(0));
+        // Only the canonical form decodes, so one ID never has two URLs
+        assert_eq!(decode_base32("3ZZZZZZ"), Err(FeistelError::InvalidEncoding));
+        assert_eq!(decode_base62("0001"), Err(FeistelError::InvalidEncoding));
+        assert_eq!(decode_base32("01"), Err(FeistelError::InvalidEncoding));
+        assert_eq!(decode_base32("1o"), Err(FeistelError::InvalidEncoding));
+        assert_eq!(decode_base62("not base62!"), Err(FeistelError::InvalidEncoding));
+        assert_eq!(decode_base62(""), Err(FeistelError::InvalidEncoding));
+        assert_eq!(decode_base32(&"z".repeat(27)), Err(FeistelError::InvalidEncoding)); // overflows u128
+    }
+}
@@ -0,0 +1,189 @@
+use core::convert::TryFrom;
+
+use sha3::digest::{ExtendableOutput, Input, XofReader};
+use sha3::Shake256;
+
+use crate::cipher::FeistelCipher;
+use crate::error::FeistelError;
+use crate::round::{RoundFunction, Sha3Round};
+
+const SHUFFLE_CONTEXT: &[u8] = b"feistel_rs shuffle";
+
+// A keyed bijection on 0..domain for any domain size.
+// Values are encrypted with the bit granular cipher on the smallest bit length that holds domain - 1
+// (at least 2 bits). Results outside the domain are encrypted again (cycle walking) until they land
+// in range. As 2^bits < 2 * domain, less than half of all values are outside the domain and the
+// expected number of encryptions per call is below 2.
+pub struct DomainPermutation<F: RoundFunction = Sha3Round> {
+    cipher: FeistelCipher<F>,
+    domain: u64,
+    bits: usize,
+}
+
+impl DomainPermutation {
+    pub fn new(key: &[u8], rounds: u32, domain: u64) -> Result<Self, FeistelError> {
+        DomainPermutation::from_cipher(FeistelCipher::try_new(key, rounds)?, domain)
+    }
+}
+
+impl<F: RoundFunction> DomainPermutation<F> {
+    pub fn from_cipher(cipher: FeistelCipher<F>, domain: u64) -> Result<Self, FeistelError> {
+        if domain == 0 {
+            return Err(FeistelError::EmptyDomain);
+        }
+        let bits = (64 - (domain - 1).leading_zeros() as usize).max(2);
+        Ok(DomainPermutation { cipher, domain, bits })
+    }
+
+    pub fn domain(&self) -> u64 {
+        self.domain
+    }
+
+    // Panics if x is not in 0..domain
+    pub fn permute(&self, x: u64) -> u64 {
+        self.walk(x, FeistelCipher::encrypt_bits_in)
+    }
+
+    // Panics if y is not in 0..domain
+    pub fn inverse(&self, y: u64) -> u64 {
+        self.walk(y, FeistelCipher::decrypt_bits_in)
+    }
+
+    // Iterates over permute(0), permute(1), .. so every value in 0..domain comes up exactly once,
+    // in pseudorandom order and without materialising the range
+    pub fn iter(&self) -> PermutationIter<'_, F> {
+        PermutationIter { permutation: self, next: 0 }
+    }
+
+    // The bit length is checked in the constructor, and at most 64 bits make halves of at most
+    // 4 bytes, so the scratch buffer of the bit network fits on the stack
+    fn walk<C>(&self, x: u64, step: C) -> u64
+    where C: Fn(&FeistelCipher<F>, &mut [u8], usize, &mut [u8]) {
+        assert!(x < self.domain, "{} is outside the domain 0..{}", x, self.domain);
+        let shift = 64 - self.bits;
+        let mut scratch = [0u8; 8];
+        let mut value = x;
+        loop {
+            let mut data = (value << shift).to_be_bytes();
+            step(&self.cipher, &mut data, self.bits, &mut scratch);
+            value = u64::from_be_bytes(data) >> shift;
+            if value < self.domain {
+                return value;
+            }
+        }
+    }
+}
+
+pub struct PermutationIter<'a, F: RoundFunction> {
+    permutation: &'a DomainPermutation<F>,
+    next: u64,
+}
+
+impl<F: RoundFunction> Iterator for PermutationIter<'_, F> {
+    type Item = u64;
+
+    fn next(&mut self) -> Option<u64> {
+        if self.next == self.permutation.domain {
+            return None;
+        }
+        self.next += 1;
+        Some(self.permutation.permute(self.next - 1))
+    }
+
+    // Domains beyond usize::MAX are possible on 32 bit targets, so this is not an ExactSizeIterator
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        match usize::try_from(self.permutation.domain - self.next) {
+            Ok(remaining) => (remaining, Some(remaining)),
+            Err(_) => (usize::MAX, None),
+        }
+    }
+}
+
+// Shuffles the slice in place with a Fisher-Yates shuffle that draws its swap positions from
+// SHAKE256 over the key, in O(n) time. The same key always gives the same order.
+pub fn shuffle_slice<T>(slice: &mut [T], key: &[u8]) -> Result<(), FeistelError> {
+    if key.is_empty() {
+        return Err(FeistelError::EmptyKey);
+    }
+    let mut shake = Shake256::default();
+    shake.input((SHUFFLE_CONTEXT.len() as u64).to_le_bytes());
+    shake.input(SHUFFLE_CONTEXT);
+    shake.input((key.len() as u64).to_le_bytes());
+    shake.input(key);
+    let mut reader = shake.xof_result();
+    for i in (1..slice.len()).rev() {
+        let j = uniform_below(&mut reader, i as u64 + 1);
+        slice.swap(i, j as usize);
+    }
+    Ok(())
+}
+
+// Uniform value in 0..bound. Draws from the last 2^64 mod bound values would make the low results
+// more likely, so they are rejected and drawn again.
+fn uniform_below<X: XofReader>(reader: &mut X, bound: u64) -> u64 {
+    let rejected = (u64::MAX % bound + 1) % bound;
+    loop {
+        let mut bytes = [0u8; 8];
+        reader.read(&mut bytes);
+        let value = u64::from_le_bytes(bytes);
+        if value <= u64::MAX - rejected {
+            return value % bound;
+        }
+    }
+}
+
+#[cfg(all(test, feature = "std"))]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn permutes_small_domains() {
+        for &domain in [1u64, 2, 3, 5, 1000, 1025].iter() {
+            let permutation = DomainPermutation::new(b"secret", 8, domain).unwrap();
+            let mut seen = vec![false; domain as usize];
+            for x in 0..domain {
+                let y = permutation.permute(x);
+                assert!(!seen[y as usize]);
+                seen[y as usize] = true;
+                assert_eq!(permutation.inverse(y), x);
+            }
+        }
+    }
+
+    #[test]
+    fn handles_full_u64_domain() {
+        let permutation = DomainPermutation::new(b"secret", 8, u64::MAX).unwrap();
+        for &x in [0, 1, u64::MAX / 2, u64::MAX - 1].iter() {
+            assert_eq!(permutation.inverse(permutation.permute(x)), x);
+        }
+        assert_eq!(DomainPermutation::new(b"secret", 8, 0).err(), Some(FeistelError::EmptyDomain));
+    }
+
+    #[test]
+    fn iterates_every_value_once() {
+        let permutation = DomainPermutation::new(b"secret", 8, 777).unwrap();
+        let mut values: Vec<u64> = permutation.iter().collect();
+        assert_eq!(permutation.iter().size_hint(), (777, Some(777)));
+        assert_ne!(values, (0..777).collect::<Vec<_>>());
+        values.sort_unstable();
+        assert_eq!(values, (0..777).collect::<Vec<_>>());
+    }
+
+    #[test]
+    fn shuffles_by_key() {
+        let shuffled = |key: &[u8]| {
+            let mut slice: Vec<u64> = (0..500).collect();
+            shuffle_slice(&mut slice, key).map(|_| slice)
+        };
+        let slice = shuffled(b"secret").unwrap();
+        assert_eq!(slice, shuffled(b"secret").unwrap());
+        assert_ne!(slice, shuffled(b"other").unwrap());
+        assert_ne!(slice, (0..500).collect::<Vec<_>>());
+        let mut sorted = slice.clone();
+        sorted.sort_unstable();
+        assert_eq!(sorted, (0..500).collect::<Vec<_>>());
+        assert_eq!(shuffled(b"").err(), Some(FeistelError::EmptyKey));
+        let mut empty: [u8; 0] = [];
+        assert!(shuffle_slice(&mut empty, b"secret").is_ok());
+    }
+}
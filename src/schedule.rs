@@ -0,0 +1,104 @@
+use sha3::digest::{ExtendableOutput, Input, XofReader};
+use sha3::Shake256;
+
+// A key schedule derives the subkey for every round from the master key.
+// Decryption asks for the same round indices in reverse order, so a schedule never has to
+// know in which direction the network runs.
+pub trait KeySchedule {
+    // Length of the subkeys this schedule derives from `key`
+    fn subkey_len(&self, key: &[u8]) -> usize;
+    // Writes the subkey for `round` into `out`, which is exactly subkey_len(key) bytes long
+    fn derive(&self, key: &[u8], round: u32, out: &mut [u8]);
+}
+
+// The original schedule of this crate: every key byte is rotated left by popcount(key) + round.
+// Note that rotating a u8 only gives 8 distinct subkeys.
+#[derive(Clone, Copy, Debug, Default)]
+pub struct RotateSalt;
+
+impl KeySchedule for RotateSalt {
+    fn subkey_len(&self, key: &[u8]) -> usize {
+        key.len()
+    }
+
+    fn derive(&self, key: &[u8], round: u32, out: &mut [u8]) {
+        let salt = key.iter().fold(0, |x, b| x + b.count_ones()) + round;
+        for (o, k) in out.iter_mut().zip(key) {
+            *o = k.rotate_left(salt);
+        }
+    }
+}
+
+// The default schedule. Every subkey is squeezed from SHAKE256 over the context string, the master
+// key and the round index, so subkeys are independent and do not repeat (no slide attacks).
+// All inputs are length prefixed to keep the encoding unambiguous.
+#[derive(Clone, Copy, Debug)]
+pub struct ShakeSchedule<C = &'static [u8]> {
+    context: C,
+}
+
+impl ShakeSchedule {
+    pub const DEFAULT_CONTEXT: &'static [u8] = b"feistel_rs shake key schedule";
+    pub const SUBKEY_LEN: usize = 32;
+}
+
+impl<C: AsRef<[u8]>> ShakeSchedule<C> {
+    // A schedule with its own context string. Different contexts give unrelated subkeys for the same key
+    pub fn new(context: C) -> Self {
+        ShakeSchedule { context }
+    }
+}
+
+impl Default for ShakeSchedule {
+    fn default() -> Self {
+        ShakeSchedule::new(ShakeSchedule::DEFAULT_CONTEXT)
+    }
+}
+
+impl<C: AsRef<[u8]>> KeySchedule for ShakeSchedule<C> {
+    fn subkey_len(&self, _key: &[u8]) -> usize {
+        ShakeSchedule::SUBKEY_LEN
+    }
+
+    fn derive(&self, key: &[u8], round: u32, out: &mut [u8]) {
+        let context = self.context.as_ref();
+        let mut shake = Shake256::default();
+        shake.input((context.len() as u64).to_le_bytes());
+        shake.input(context);
+        shake.input((key.len() as u64).to_le_bytes());
+        shake.input(key);
+        shake.input(round.to_le_bytes());
+        shake.xof_result().read(out);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn rotate_salt_repeats_after_eight_rounds() {
+        let key = b"\x01\x80\xff";
+        let mut first = [0u8; 3];
+        let mut ninth = [0u8; 3];
+        RotateSalt.derive(key, 0, &mut first);
+        RotateSalt.derive(key, 8, &mut ninth);
+        // popcount is 10, so the bytes are rotated by 10 % 8 = 2
+        assert_eq!(first, [0x04, 0x02, 0xff]);
+        assert_eq!(first, ninth);
+    }
+
+    #[test]
+    fn shake_schedule_gives_independent_subkeys() {
+        let key = b"\x01\x80\xff";
+        let schedule = ShakeSchedule::default();
+        let mut first = [0u8; ShakeSchedule::SUBKEY_LEN];
+        let mut ninth = [0u8; ShakeSchedule::SUBKEY_LEN];
+        let mut other_context = [0u8; ShakeSchedule::SUBKEY_LEN];
+        schedule.derive(key, 0, &mut first);
+        schedule.derive(key, 8, &mut ninth);
+        ShakeSchedule::new(b"another context").derive(key, 0, &mut other_context);
+        assert_ne!(first, ninth);
+        assert_ne!(first, other_context);
+    }
+}
@@ -0,0 +1,220 @@
+// Online authenticated encryption in segments, following the STREAM construction of Hoang,
+// Reyhanitabar, Rogaway and Vizár. Every segment is sealed with FeistelAead under the nonce
+//
+//     nonce prefix (7 bytes) || segment counter (u32 big endian) || last segment flag (1 byte)
+//
+// so segments cannot be reordered, dropped or duplicated, and a stream cut off after a complete
+// segment is detected because its last segment was not sealed with the flag set. The keys are
+// derived with a context of their own, so a segment nonce never shares keystream with the same
+// nonce passed to FeistelAead directly under the same master key.
+//
+// The format is the nonce prefix followed by the sealed segments (ciphertext || 32 byte tag).
+// Every segment but the last holds exactly StreamEncryptor::SEGMENT_LEN bytes of plaintext, the
+// last one at most that many and may be empty. Memory use is bounded by one segment on either side.
+
+#[cfg(feature = "alloc")]
+use alloc::vec::Vec;
+
+use crate::aead::FeistelAead;
+use crate::error::FeistelError;
+
+const NONCE_PREFIX_LEN: usize = 7;
+const CONTEXT: &[u8] = b"feistel_rs stream ctr hmac-sha3-256";
+
+#[derive(Clone, Debug)]
+struct SegmentNonces {
+    prefix: [u8; NONCE_PREFIX_LEN],
+    counter: u64,
+}
+
+impl SegmentNonces {
+    fn next(&mut self, last: bool) -> Result<[u8; 12], FeistelError> {
+        if self.counter > u64::from(u32::MAX) {
+            return Err(FeistelError::KeystreamExhausted);
+        }
+        let mut nonce = [0u8; 12];
+        nonce[..NONCE_PREFIX_LEN].copy_from_slice(&self.prefix);
+        nonce[NONCE_PREFIX_LEN..11].copy_from_slice(&(self.counter as u32).to_be_bytes());
+        nonce[11] = last as u8;
+        self.counter += 1;
+        Ok(nonce)
+    }
+}
+
+fn check_segment(len: usize, last: bool) -> Result<(), FeistelError> {
+    let segment_len = StreamEncryptor::SEGMENT_LEN;
+    if len > segment_len || (!last && len != segment_len) {
+        return Err(FeistelError::InvalidSegmentLength { len });
+    }
+    Ok(())
+}
+
+// Not Clone: a copy would seal its next segment under a nonce the original uses as well
+#[derive(Debug)]
+pub struct StreamEncryptor {
+    aead: FeistelAead,
+    nonces: SegmentNonces,
+}
+
+impl StreamEncryptor {
+    pub const NONCE_PREFIX_LEN: usize = NONCE_PREFIX_LEN;
+    // Plaintext bytes in every segment but the last. The unit tests use short segments, so they
+    // cover multi-segment streams without hashing megabytes in debug builds.
+    pub const SEGMENT_LEN: usize = if cfg!(test) { 1024 } else { 64 * 1024 };
+
+    // The nonce prefix must be unique per stream under the same key, a random one is fine
+    pub fn new(key: &[u8], nonce_prefix: &[u8; NONCE_PREFIX_LEN]) -> Result<Self, FeistelError> {
+        let nonces = SegmentNonces { prefix: *nonce_prefix, counter: 0 };
+        Ok(StreamEncryptor { aead: FeistelAead::with_context(key, CONTEXT)?, nonces })
+    }
+
+    // Every segment but the last has to be exactly SEGMENT_LEN bytes long
+    #[cfg(feature = "alloc")]
+    pub fn encrypt_next(&mut self, aad: &[u8], segment: &[u8]) -> Result<Vec<u8>, FeistelError> {
+        check_segment(segment.len(), false)?;
+        self.aead.seal(&self.nonces.next(false)?, aad, segment)
+    }
+
+    // Seals the final segment of at most SEGMENT_LEN bytes, after which the stream is complete
+    #[cfg(feature = "alloc")]
+    pub fn encrypt_last(mut self, aad: &[u8], segment: &[u8]) -> Result<Vec<u8>, FeistelError> {
+        check_segment(segment.len(), true)?;
+        self.aead.seal(&self.nonces.next(true)?, aad, segment)
+    }
+
+    pub fn encrypt_next_in_place(&mut self, aad: &[u8], buf: &mut [u8]) -> Result<[u8; 32], FeistelError> {
+        check_segment(buf.len(), false)?;
+        self.aead.seal_in_place_detached(&self.nonces.next(false)?, aad, buf)
+    }
+
+    pub fn encrypt_last_in_place(mut self, aad: &[u8], buf: &mut [u8]) -> Result<[u8; 32], FeistelError> {
+        check_segment(buf.len(), true)?;
+        self.aead.seal_in_place_detached(&self.nonces.next(true)?, aad, buf)
+    }
+}
+
+#[derive(Clone, Debug)]
+pub struct StreamDecryptor {
+    aead: FeistelAead,
+    nonces: SegmentNonces,
+}
+
+impl StreamDecryptor {
+    pub fn new(key: &[u8], nonce_prefix: &[u8; NONCE_PREFIX_LEN]) -> Result<Self, FeistelError> {
+        let nonces = SegmentNonces { prefix: *nonce_prefix, counter: 0 };
+        Ok(StreamDecryptor { aead: FeistelAead::with_context(key, CONTEXT)?, nonces })
+    }
+
+    // A failed segment still uses up its position, so the stream cannot continue after an error
+    #[cfg(feature = "alloc")]
+    pub fn decrypt_next(&mut self, aad: &[u8], sealed: &[u8]) -> Result<Vec<u8>, FeistelError> {
+        check_segment(sealed.len().saturating_sub(FeistelAead::TAG_LEN), false)?;
+        self.aead.open(&self.nonces.next(false)?, aad, sealed)
+    }
+
+    #[cfg(feature = "alloc")]
+    pub fn decrypt_last(mut self, aad: &[u8], sealed: &[u8]) -> Result<Vec<u8>, FeistelError> {
+        check_segment(sealed.len().saturating_sub(FeistelAead::TAG_LEN), true)?;
+        self.aead.open(&self.nonces.next(true)?, aad, sealed)
+    }
+
+    pub fn decrypt_next_in_place(&mut self, aad: &[u8], buf: &mut [u8], tag: &[u8]) -> Result<(), FeistelError> {
+        check_segment(buf.len(), false)?;
+        self.aead.open_in_place_detached(&self.nonces.next(false)?, aad, buf, tag)
+    }
+
+    pub fn decrypt_last_in_place(mut self, aad: &[u8], buf: &mut [u8], tag: &[u8]) -> Result<(), FeistelError> {
+        check_segment(buf.len(), true)?;
+        self.aead.open_in_place_detached(&self.nonces.next(true)?, aad, buf, tag)
+    }
+}
+
+#[cfg(all(test, feature = "std"))]
+mod tests {
+    use super::*;
+
+    const KEY: &[u8] = b"backup key";
+    const PREFIX: &[u8; 7] = b"stream1";
+
+    const SEGMENT_LEN: usize = StreamEncryptor::SEGMENT_LEN;
+
+    fn sealed_segments() -> Vec<Vec<u8>> {
+        let mut encryptor = StreamEncryptor::new(KEY, PREFIX).unwrap();
+        let mut segments = vec![
+            encryptor.encrypt_next(b"", &[1; SEGMENT_LEN]).unwrap(),
+            encryptor.encrypt_next(b"", &[2; SEGMENT_LEN]).unwrap(),
+        ];
+        segments.push(encryptor.encrypt_last(b"", b"end").unwrap());
+        segments
+    }
+
+    #[test]
+    fn stream_round_trip() {
+        let segments = sealed_segments();
+        let mut decryptor = StreamDecryptor::new(KEY, PREFIX).unwrap();
+        assert_eq!(decryptor.decrypt_next(b"", &segments[0]).unwrap(), [1; SEGMENT_LEN]);
+        assert_eq!(decryptor.decrypt_next(b"", &segments[1]).unwrap(), [2; SEGMENT_LEN]);
+        assert_eq!(decryptor.decrypt_last(b"", &segments[2]).unwrap(), b"end");
+
+        let encryptor = StreamEncryptor::new(KEY, PREFIX).unwrap();
+        let mut buf = *b"in place";
+        let tag = encryptor.encrypt_last_in_place(b"", &mut buf).unwrap();
+        let decryptor = StreamDecryptor::new(KEY, PREFIX).unwrap();
+        decryptor.decrypt_last_in_place(b"", &mut buf, &tag).unwrap();
+        assert_eq!(&buf, b"in place");
+    }
+
+    #[test]
+    fn stream_detects_reordering_and_truncation() {
+        let segments = sealed_segments();
+        let failed = Err(FeistelError::AuthenticationFailed);
+        // Swapped segments
+        let mut decryptor = StreamDecryptor::new(KEY, PREFIX).unwrap();
+        assert_eq!(decryptor.decrypt_next(b"", &segments[1]), failed);
+        // Cut off after the first segment
+        let decryptor = StreamDecryptor::new(KEY, PREFIX).unwrap();
+        assert_eq!(decryptor.decrypt_last(b"", &segments[0]), failed);
+        // Dropped middle segment
+        let mut decryptor = StreamDecryptor::new(KEY, PREFIX).unwrap();
+        decryptor.decrypt_next(b"", &segments[0]).unwrap();
+        assert_eq!(decryptor.decrypt_last(b"", &segments[2]), failed);
+        // Other stream
+        let mut decryptor = StreamDecryptor::new(KEY, b"stream2").unwrap();
+        assert_eq!(decryptor.decrypt_next(b"", &segments[0]), failed);
+    }
+
+    #[test]
+    fn stream_keys_are_separate_from_aead() {
+        let encryptor = StreamEncryptor::new(KEY, PREFIX).unwrap();
+        let segment = encryptor.encrypt_last(b"", &[0; 16]).unwrap();
+        let mut nonce = [1u8; 12];
+        nonce[..7].copy_from_slice(PREFIX);
+        nonce[7..11].copy_from_slice(&[0; 4]);
+        let direct = FeistelAead::new(KEY).unwrap().seal(&nonce, b"", &[0; 16]).unwrap();
+        assert_ne!(segment[..16], direct[..16]);
+    }
+
+    #[test]
+    fn stream_refuses_counter_overflow() {
+        let mut encryptor = StreamEncryptor::new(KEY, PREFIX).unwrap();
+        encryptor.nonces.counter = u64::from(u32::MAX);
+        assert!(encryptor.encrypt_next(b"", &[0; SEGMENT_LEN]).is_ok());
+        assert_eq!(encryptor.encrypt_next(b"", &[0; SEGMENT_LEN]), Err(FeistelError::KeystreamExhausted));
+    }
+
+    #[test]
+    fn stream_enforces_segment_length() {
+        let mut encryptor = StreamEncryptor::new(KEY, PREFIX).unwrap();
+        let invalid = |len| Err(FeistelError::InvalidSegmentLength { len });
+        assert_eq!(encryptor.encrypt_next(b"", b"abc"), invalid(3));
+        let result = encryptor.encrypt_next_in_place(b"", &mut [0; SEGMENT_LEN + 1]);
+        assert_eq!(result.err(), Some(FeistelError::InvalidSegmentLength { len: SEGMENT_LEN + 1 }));
+        let fresh = StreamEncryptor::new(KEY, PREFIX).unwrap();
+        assert_eq!(fresh.encrypt_last(b"", &[0; SEGMENT_LEN + 1]), invalid(SEGMENT_LEN + 1));
+        assert!(encryptor.encrypt_last(b"", &[0; SEGMENT_LEN]).is_ok());
+        let mut decryptor = StreamDecryptor::new(KEY, PREFIX).unwrap();
+        let short = StreamEncryptor::new(KEY, PREFIX).unwrap().encrypt_last(b"", b"abc").unwrap();
+        assert_eq!(decryptor.decrypt_next(b"", &short), invalid(3));
+        assert_eq!(decryptor.decrypt_last(b"", &short), Ok(b"abc".to_vec()));
+    }
+}